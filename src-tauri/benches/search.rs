@@ -0,0 +1,253 @@
+//// Search benchmark
+// Compares the latency of searching a synthetic index the old way with the current way.
+// Before, search_files cloned the whole HashMap<String, String> of paths to file names on
+// every query, built a new Rayon pool and fuzzy matched every entry. After, the search
+// module of the app scores the same entries in the shared CompactIndex on the long-lived
+// pool. Both sides score every entry, so the trigram narrowing of find_matches is timed
+// on its own in the last column.
+//
+// Run with `cargo bench --bench search`, optionally followed by `-- <entries>...` to pick
+// the index sizes. The default sizes of 100k, 1M and 5M entries need a few GB of memory.
+
+//// Imports
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use once_cell::sync::Lazy;
+use rayon::prelude::*;
+use rust_finder_lib::compact_index::CompactIndex;
+use rust_finder_lib::normalize::Folding;
+use rust_finder_lib::query::{parse_query, SearchQuery};
+use rust_finder_lib::search::{
+    find_matches, match_entries, sort_matches, Ranking, SearchToken, SortBy,
+};
+use rust_finder_lib::FileDetails;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+//// Constants
+const DEFAULT_SIZES: [usize; 3] = [100_000, 1_000_000, 5_000_000];
+const RUNS: usize = 5; // Runs per query, the median is reported
+const FILES_PER_DIRECTORY: usize = 40;
+const OLD_MINIMUM_SCORE: i64 = 20; // Scoring of search_files before the search module
+const OLD_STEM_WEIGHT: i64 = 2;
+// Single terms, which the old search and the search module match alike
+const QUERIES: [&str; 5] = ["main", "report", "src/lib", "zzqx", "fi"];
+const WORDS: [&str; 16] = [
+    "report", "invoice", "main", "lib", "notes", "budget", "photo", "draft", "config", "test",
+    "summary", "backup", "index", "readme", "schema", "final",
+];
+const EXTENSIONS: [&str; 8] = ["pdf", "docx", "rs", "txt", "png", "xlsx", "js", "md"];
+const FOLDERS: [&str; 8] = [
+    "src", "docs", "assets", "archive", "projects", "photos", "work", "2023",
+];
+
+// Matcher of the old search
+static MATCHER: Lazy<SkimMatcherV2> = Lazy::new(|| SkimMatcherV2::default().ignore_case());
+
+//// Synthetic Index
+/// Small deterministic generator, so every run searches the same index
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.next() % items.len()]
+    }
+}
+
+/// Builds an index of the given size with nested folders of FILES_PER_DIRECTORY files,
+/// along with the old map of the same paths to their file names
+fn build_index(size: usize) -> (CompactIndex, HashMap<String, String>) {
+    let mut random = Lcg(size as u64);
+    let mut index = CompactIndex::default();
+    let mut paths = HashMap::with_capacity(size);
+    let mut directory = String::new();
+
+    for i in 0..size {
+        if i % FILES_PER_DIRECTORY == 0 {
+            let depth = 2 + random.next() % 4;
+            directory = String::from("/home/user");
+            for _ in 0..depth {
+                directory.push('/');
+                directory.push_str(random.pick(&FOLDERS));
+            }
+            directory.push_str(&format!("/{}", i / FILES_PER_DIRECTORY));
+        }
+
+        let extension = random.pick(&EXTENSIONS);
+        let file_name = format!(
+            "{}_{}_{}.{}",
+            random.pick(&WORDS),
+            random.pick(&WORDS),
+            i,
+            extension
+        );
+        let file_path = format!("{}/{}", directory, file_name);
+        paths.insert(file_path.clone(), file_name.clone());
+        index.insert(&FileDetails {
+            file_path,
+            file_name,
+            file_size: (random.next() % 1_000_000) as u64,
+            file_type: String::from("file"),
+            creation_date: None,
+            modified_date: None,
+            file_extension: extension.to_string(),
+            ignored: false,
+            scanned: false,
+            raw_path: None,
+        });
+    }
+    (index, paths)
+}
+
+//// Searching
+/// Scores like score_filename did before the search module
+fn old_score_filename(file_name: &str, file_path: &str, query: &str) -> Option<i64> {
+    let file_stem = Path::new(file_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(file_name);
+    let stem_score = MATCHER
+        .fuzzy_match(file_stem, query)
+        .map(|score| score * OLD_STEM_WEIGHT);
+    stem_score
+        .max(MATCHER.fuzzy_match(file_path, query))
+        .filter(|score| *score >= OLD_MINIMUM_SCORE)
+}
+
+/// The search before the shared index, as search_files did it: a full copy of the map of
+/// paths to file names, a new pool and a full scan, sorted by score
+fn search_before(shared: &Mutex<HashMap<String, String>>, query: &str) -> usize {
+    let index_snapshot = shared.lock().unwrap().clone();
+    let query = query.trim();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_cpus::get())
+        .build()
+        .expect("pool can be built");
+
+    let mut results: Vec<(i64, String, String)> = pool.install(|| {
+        index_snapshot
+            .par_iter()
+            .filter_map(|(file_path, file_name)| {
+                old_score_filename(file_name, file_path, query)
+                    .map(|score| (score, file_name.clone(), file_path.clone()))
+            })
+            .collect()
+    });
+    results.sort_unstable_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then_with(|| a.2.len().cmp(&b.2.len()))
+            .then_with(|| a.2.cmp(&b.2))
+    });
+    results.len()
+}
+
+/// The search module scoring every entry under the read lock of the shared index, on the
+/// same entries as search_before
+fn search_after(shared: &RwLock<CompactIndex>, query: &SearchQuery, ranking: &Ranking) -> usize {
+    let index = shared.read().unwrap();
+    let mut matches = match_entries(&index, None, query, ranking, SearchToken::start());
+    sort_matches(&index, &mut matches, SortBy::Score);
+    matches.len()
+}
+
+/// The current search of the app, which scores the entries containing the query first
+fn search_narrowed(shared: &RwLock<CompactIndex>, query: &SearchQuery, ranking: &Ranking) -> usize {
+    let index = shared.read().unwrap();
+    let mut matches = find_matches(&index, query, ranking, SearchToken::start(), &mut |_| {})
+        .expect("nothing cancels the benchmark searches");
+    sort_matches(&index, &mut matches, SortBy::Score);
+    matches.len()
+}
+
+//// Measuring
+fn median(mut run: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut matches = 0;
+    let mut timings: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            matches = run();
+            started.elapsed()
+        })
+        .collect();
+    timings.sort_unstable();
+    (timings[RUNS / 2], matches)
+}
+
+fn main() {
+    // Cargo passes --bench to the harness, only numbers are sizes
+    let sizes: Vec<usize> = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.replace('_', "").parse().ok())
+        .collect();
+    let sizes = if sizes.is_empty() {
+        DEFAULT_SIZES.to_vec()
+    } else {
+        sizes
+    };
+    let ranking = Ranking {
+        extensions: HashMap::new(),
+        frecency: HashMap::new(),
+        now: SystemTime::now(),
+    };
+
+    for size in sizes {
+        let started = Instant::now();
+        let (index, paths) = build_index(size);
+        let (shared, old_shared) = (RwLock::new(index), Mutex::new(paths));
+        let built = started.elapsed();
+
+        // The search module logs every search, so the table is printed once all ran
+        let rows: Vec<_> = QUERIES
+            .iter()
+            .map(|query| {
+                let mut parsed = parse_query(query).expect("benchmark queries are valid");
+                parsed.set_folding(Folding::default());
+                let (before, old_matches) = median(|| search_before(&old_shared, query));
+                let (after, matches) = median(|| search_after(&shared, &parsed, &ranking));
+                let (narrowed, narrowed_matches) =
+                    median(|| search_narrowed(&shared, &parsed, &ranking));
+                (
+                    query,
+                    before,
+                    after,
+                    old_matches,
+                    matches,
+                    narrowed,
+                    narrowed_matches,
+                )
+            })
+            .collect();
+
+        println!(
+            "\n{} entries, built in {:.1?}, median of {} runs",
+            size, built, RUNS
+        );
+        println!(
+            "{:<14} {:>12} {:>12} {:>9} {:>9} {:>9} {:>12} {:>9}",
+            "query", "before", "after", "speedup", "old hits", "matches", "narrowed", "matches"
+        );
+        for (query, before, after, old_matches, matches, narrowed, narrowed_matches) in rows {
+            println!(
+                "{:<14} {:>12.2?} {:>12.2?} {:>8.1}x {:>9} {:>9} {:>12.2?} {:>9}",
+                format!("{:?}", query),
+                before,
+                after,
+                before.as_secs_f64() / after.as_secs_f64(),
+                old_matches,
+                matches,
+                narrowed,
+                narrowed_matches
+            );
+        }
+    }
+}
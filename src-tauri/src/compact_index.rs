@@ -0,0 +1,604 @@
+//// Compact in-memory index
+// Keeps every FileDetails field for millions of entries without a String per field:
+// file names live in one shared buffer, parent directories and extensions are interned,
+// and dates are packed into plain integers. Trigram indexes over the normalized file names
+// and directory names let substring searches skip the entries that can't match.
+//
+// Names and directories are folded once when they are inserted, in the loose folding
+// that searches use by default, instead of normalizing every entry on every keystroke.
+// Only non-ASCII text is stored again, since folding keeps ASCII as it is.
+
+//// Imports
+use crate::normalize::Folding;
+use crate::raw_path;
+use crate::trigram_index::TrigramIndex;
+use crate::FileDetails;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//// Constants
+const NO_DATE: u64 = u64::MAX; // Packed value for a missing date
+const COMPACT_MINIMUM: usize = 10_000; // Removed entries always tolerated before compacting
+pub const FILE_TYPES: [&str; 3] = ["file", "directory", "unknown"];
+
+//// Global Variables
+// Source of generation numbers, unique across all indexes
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+//// Data Structures
+/// Fixed-size record for one indexed entry
+#[derive(Debug, Clone)]
+pub struct CompactEntry {
+    name_start: u32,    // Offset of the file name in CompactIndex.names
+    name_len: u32,      // Length of the file name in bytes
+    directory: u32,     // Id of the parent directory
+    extension: u32,     // Id of the interned extension
+    file_type: u8,      // Position in FILE_TYPES
+    removed: bool,      // Slot is free for reuse
+    ignored: bool,      // Matched by an ignore file
+    scanned: bool,      // Directory whose entries were read
+    file_size: u64,     // Size in bytes
+    creation_date: u64, // Nanoseconds since the epoch, or NO_DATE
+    modified_date: u64, // Nanoseconds since the epoch, or NO_DATE
+}
+
+/// Borrowed view of an entry with the same fields as FileDetails
+#[derive(Debug, Clone, Copy)]
+pub struct FileView<'a> {
+    pub file_name: &'a str,
+    pub file_path: &'a str,
+    pub file_size: u64,
+    pub file_type: &'static str,
+    pub creation_date: Option<SystemTime>,
+    pub modified_date: Option<SystemTime>,
+    pub file_extension: &'a str,
+    pub ignored: bool,
+    pub scanned: bool,
+}
+
+/// In-memory index with interned directory prefixes and extensions
+#[derive(Debug, Clone, Default)]
+pub struct CompactIndex {
+    entries: Vec<CompactEntry>,
+    free_slots: Vec<u32>,
+    names: String, // File names of all entries back to back
+    directories: Vec<Box<str>>,
+    folded_directories: Vec<Option<Box<str>>>, // Folded paths of non-ASCII directories
+    directory_ids: HashMap<Box<str>, u32>,
+    directory_entries: Vec<Vec<u32>>, // Slots of the entries inside each directory
+    directory_children: Vec<Vec<u32>>, // Ids of the directories inside each directory
+    name_trigrams: TrigramIndex,      // Folded file names to slots
+    directory_trigrams: TrigramIndex, // Folded last components of directories to ids
+    extensions: Vec<Box<str>>,
+    extension_ids: HashMap<Box<str>, u32>,
+    raw_paths: HashMap<u32, Box<[u8]>>, // Exact paths of slots that aren't valid Unicode
+    folded_names: HashMap<u32, Box<str>>, // Folded file names of non-ASCII slots
+    rule_fingerprints: HashMap<String, u32>, // Exclusion rules each root was fully indexed with
+    len: usize,
+    stale: usize,    // Entries removed since the index was last rebuilt
+    generation: u64, // Changes with every modification, so slots can be cached between reads
+}
+
+//// Packing Helpers
+pub fn pack_date(date: Option<SystemTime>) -> u64 {
+    date.and_then(|date| date.duration_since(UNIX_EPOCH).ok())
+        .map_or(NO_DATE, |since_epoch| {
+            since_epoch.as_nanos().min(u128::from(NO_DATE - 1)) as u64
+        })
+}
+
+pub fn unpack_date(packed: u64) -> Option<SystemTime> {
+    (packed != NO_DATE).then(|| UNIX_EPOCH + Duration::from_nanos(packed))
+}
+
+pub fn pack_file_type(file_type: &str) -> u8 {
+    FILE_TYPES
+        .iter()
+        .position(|known| *known == file_type)
+        .unwrap_or(FILE_TYPES.len() - 1) as u8
+}
+
+/// Splits a path into its parent directory and its last component
+fn split_path(file_path: &str) -> Option<(String, String)> {
+    let path = Path::new(file_path);
+    let parent = path.parent()?.display().to_string();
+    let name = path.file_name()?.to_string_lossy().to_string();
+    Some((parent, name))
+}
+
+/// Appends a directory and a file name to a buffer the same way Path::join would
+fn write_path(buffer: &mut String, directory: &str, file_name: &str) {
+    buffer.push_str(directory);
+    if !directory.ends_with(MAIN_SEPARATOR) {
+        buffer.push(MAIN_SEPARATOR);
+    }
+    buffer.push_str(file_name);
+}
+
+//// Index Operations
+impl CompactEntry {
+    pub fn is_removed(&self) -> bool {
+        self.removed
+    }
+}
+
+impl CompactIndex {
+    /// Number of live entries
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        *self = CompactIndex::default();
+        self.touch();
+    }
+
+    /// Identifies the current contents. Slots taken from the index stay valid as long
+    /// as its generation doesn't change.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn touch(&mut self) {
+        self.generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fingerprints of the exclusion rules each root was fully indexed with
+    pub fn rule_fingerprints(&self) -> &HashMap<String, u32> {
+        &self.rule_fingerprints
+    }
+
+    pub fn set_rule_fingerprints(&mut self, rule_fingerprints: HashMap<String, u32>) {
+        self.rule_fingerprints = rule_fingerprints;
+    }
+
+    /// All entry slots, including removed ones
+    pub fn entries(&self) -> &[CompactEntry] {
+        &self.entries
+    }
+
+    /// Interns a directory along with the directories above it
+    fn intern_directory(&mut self, directory: &str) -> u32 {
+        if let Some(id) = self.directory_ids.get(directory) {
+            return *id;
+        }
+        let id = self.directories.len() as u32;
+        let folded = Folding::LOOSE.fold(directory);
+        if let Some(name) = Path::new(&*folded).file_name() {
+            self.directory_trigrams
+                .insert(&name.to_string_lossy().to_ascii_lowercase(), id);
+        }
+        self.folded_directories
+            .push((!directory.is_ascii()).then(|| folded.into()));
+        self.directories.push(directory.into());
+        self.directory_ids.insert(directory.into(), id);
+        self.directory_entries.push(Vec::new());
+        self.directory_children.push(Vec::new());
+
+        let path = Path::new(directory);
+        if let Some(parent) = path.parent() {
+            let parent_id = self.intern_directory(&parent.display().to_string());
+            self.directory_children[parent_id as usize].push(id);
+        }
+        id
+    }
+
+    fn intern_extension(&mut self, extension: &str) -> u32 {
+        if let Some(id) = self.extension_ids.get(extension) {
+            return *id;
+        }
+        let id = self.extensions.len() as u32;
+        self.extensions.push(extension.into());
+        self.extension_ids.insert(extension.into(), id);
+        id
+    }
+
+    fn name_of(&self, entry: &CompactEntry) -> &str {
+        let start = entry.name_start as usize;
+        &self.names[start..start + entry.name_len as usize]
+    }
+
+    /// File name of a slot in the loose folding
+    fn folded_name_of(&self, slot: u32) -> &str {
+        match self.folded_names.get(&slot) {
+            Some(folded) => folded,
+            None => self.name_of(&self.entries[slot as usize]),
+        }
+    }
+
+    /// Directory path in the loose folding
+    fn folded_directory(&self, directory_id: u32) -> &str {
+        match &self.folded_directories[directory_id as usize] {
+            Some(folded) => folded,
+            None => &self.directories[directory_id as usize],
+        }
+    }
+
+    /// Returns the given directories along with every directory below them
+    fn subtree(&self, mut stack: Vec<u32>) -> Vec<u32> {
+        let mut visited = HashSet::new();
+        let mut subtree = Vec::new();
+        while let Some(id) = stack.pop() {
+            if visited.insert(id) {
+                subtree.push(id);
+                stack.extend_from_slice(&self.directory_children[id as usize]);
+            }
+        }
+        subtree
+    }
+
+    /// Frees a slot without touching the list of its directory
+    fn free_slot(&mut self, slot: u32) {
+        self.entries[slot as usize].removed = true;
+        self.raw_paths.remove(&slot);
+        self.folded_names.remove(&slot);
+        self.free_slots.push(slot);
+        self.len -= 1;
+    }
+
+    /// Counts removed entries and rebuilds the index once they outnumber the live ones.
+    /// Their names, trigram postings and directories stay behind until then.
+    fn add_stale(&mut self, count: usize) {
+        self.stale += count;
+        if self.stale > self.len.max(COMPACT_MINIMUM) {
+            self.compact();
+        }
+    }
+
+    /// Rebuilds the index from its live entries, which changes their slots
+    fn compact(&mut self) {
+        let mut compacted = CompactIndex::default();
+        for details in self.details() {
+            compacted.insert(&details);
+        }
+        println!(
+            "Compacted the index, dropping {} removed entries.",
+            self.stale
+        );
+        compacted.rule_fingerprints = std::mem::take(&mut self.rule_fingerprints);
+        *self = compacted;
+        self.touch();
+    }
+
+    /// Finds the slot of a path, if it is indexed
+    fn find(&self, directory: &str, file_name: &str) -> Option<u32> {
+        let directory_id = *self.directory_ids.get(directory)?;
+        self.directory_entries[directory_id as usize]
+            .iter()
+            .copied()
+            .find(|slot| self.name_of(&self.entries[*slot as usize]) == file_name)
+    }
+
+    /// Inserts an entry, replacing the previous entry for the same path
+    pub fn insert(&mut self, details: &FileDetails) {
+        let Some((directory, file_name)) = split_path(&details.file_path) else {
+            return; // The root of the file system has no parent to file it under
+        };
+        self.touch();
+
+        // Reuse the stored name when the path is already indexed
+        let existing = self.find(&directory, &file_name);
+        let (name_start, name_len) = match existing {
+            Some(slot) => {
+                let entry = &self.entries[slot as usize];
+                (entry.name_start, entry.name_len)
+            }
+            None => {
+                let name_start = self.names.len() as u32;
+                self.names.push_str(&file_name);
+                (name_start, file_name.len() as u32)
+            }
+        };
+        let folded_name = existing.is_none().then(|| Folding::LOOSE.fold(&file_name));
+
+        let directory_id = self.intern_directory(&directory);
+        let entry = CompactEntry {
+            name_start,
+            name_len,
+            directory: directory_id,
+            extension: self.intern_extension(&details.file_extension),
+            file_type: pack_file_type(&details.file_type),
+            removed: false,
+            ignored: details.ignored,
+            scanned: details.scanned,
+            file_size: details.file_size,
+            creation_date: pack_date(details.creation_date),
+            modified_date: pack_date(details.modified_date),
+        };
+
+        let slot = match existing {
+            Some(slot) => {
+                self.entries[slot as usize] = entry;
+                slot
+            }
+            None => {
+                let slot = match self.free_slots.pop() {
+                    Some(slot) => {
+                        self.entries[slot as usize] = entry;
+                        slot
+                    }
+                    None => {
+                        self.entries.push(entry);
+                        (self.entries.len() - 1) as u32
+                    }
+                };
+                self.directory_entries[directory_id as usize].push(slot);
+                if let Some(folded_name) = folded_name {
+                    self.name_trigrams
+                        .insert(&folded_name.to_ascii_lowercase(), slot);
+                    match folded_name {
+                        Cow::Owned(folded_name) => {
+                            self.folded_names.insert(slot, folded_name.into())
+                        }
+                        Cow::Borrowed(_) => self.folded_names.remove(&slot),
+                    };
+                }
+                self.len += 1;
+                slot
+            }
+        };
+        match &details.raw_path {
+            Some(raw_path) => self.raw_paths.insert(slot, raw_path.as_slice().into()),
+            None => self.raw_paths.remove(&slot),
+        };
+    }
+
+    /// Removes a single path, returning true if it was indexed
+    pub fn remove(&mut self, file_path: &str) -> bool {
+        let Some((directory, file_name)) = split_path(file_path) else {
+            return false;
+        };
+        let Some(slot) = self.find(&directory, &file_name) else {
+            return false;
+        };
+
+        self.touch();
+        let directory_id = self.entries[slot as usize].directory as usize;
+        self.directory_entries[directory_id].retain(|other| *other != slot);
+        self.free_slot(slot);
+        self.add_stale(1);
+        true
+    }
+
+    /// Removes a path and everything below it, returning true if the path was indexed
+    pub fn remove_subtree(&mut self, file_path: &str) -> bool {
+        let removed_path = self.remove(file_path);
+        let Some(directory_id) = self.directory_ids.get(file_path).copied() else {
+            return removed_path;
+        };
+        self.touch();
+
+        let mut removed = 0;
+        for id in self.subtree(vec![directory_id]) {
+            for slot in std::mem::take(&mut self.directory_entries[id as usize]) {
+                self.free_slot(slot);
+                removed += 1;
+            }
+        }
+        self.add_stale(removed);
+        removed_path
+    }
+
+    /// Returns the slots of the live entries whose file name contains the text, or whose
+    /// directory path does unless names_only is set. Case and accents are ignored.
+    /// Returns None if the text is too short for the trigram indexes. The slots are sorted.
+    pub fn substring_candidates(&self, text: &str, names_only: bool) -> Option<Vec<u32>> {
+        let text = Folding::LOOSE.key(text);
+
+        // A directory path only contains the text if one of its components does, so
+        // narrow by the longest piece between separators and let scoring check the rest
+        let piece = if names_only {
+            text.as_str()
+        } else {
+            text.split(['/', '\\'])
+                .max_by_key(|piece| piece.chars().count())
+                .unwrap_or_default()
+        };
+        let name_candidates = self.name_trigrams.candidates(piece)?;
+
+        let mut slots: Vec<u32> = name_candidates
+            .iter()
+            .copied()
+            .filter(|slot| {
+                let entry = &self.entries[*slot as usize];
+                !entry.removed
+                    && self
+                        .folded_name_of(*slot)
+                        .to_ascii_lowercase()
+                        .contains(piece)
+            })
+            .collect();
+
+        if !names_only {
+            // Everything below a matching directory matches through its path
+            let matching: Vec<u32> = self
+                .directory_trigrams
+                .candidates(piece)
+                .unwrap_or_default()
+                .iter()
+                .copied()
+                .filter(|id| {
+                    Path::new(self.folded_directory(*id))
+                        .file_name()
+                        .is_some_and(|name| {
+                            name.to_string_lossy().to_ascii_lowercase().contains(piece)
+                        })
+                })
+                .collect();
+            for id in self.subtree(matching) {
+                slots.extend_from_slice(&self.directory_entries[id as usize]);
+            }
+        }
+
+        slots.sort_unstable();
+        slots.dedup();
+        Some(slots)
+    }
+
+    /// Returns a view of an entry, building its full path in the given buffer
+    pub fn view<'a>(
+        &'a self,
+        entry: &'a CompactEntry,
+        path_buffer: &'a mut String,
+    ) -> FileView<'a> {
+        let file_name = self.name_of(entry);
+        path_buffer.clear();
+        write_path(
+            path_buffer,
+            &self.directories[entry.directory as usize],
+            file_name,
+        );
+
+        FileView {
+            file_name,
+            file_path: path_buffer,
+            file_size: entry.file_size,
+            file_type: FILE_TYPES[entry.file_type as usize],
+            creation_date: unpack_date(entry.creation_date),
+            modified_date: unpack_date(entry.modified_date),
+            file_extension: &self.extensions[entry.extension as usize],
+            ignored: entry.ignored,
+            scanned: entry.scanned,
+        }
+    }
+
+    /// Returns the file name and full path of a slot folded for the fuzzy matcher, building
+    /// the path in the given buffer. The loose folding was stored when the entry was
+    /// inserted, any other folding is applied here, straight into the buffer.
+    pub fn folded<'a>(
+        &'a self,
+        slot: u32,
+        folding: Folding,
+        path_buffer: &'a mut String,
+    ) -> (&'a str, &'a str) {
+        let entry = &self.entries[slot as usize];
+        path_buffer.clear();
+        if folding == Folding::LOOSE {
+            let file_name = self.folded_name_of(slot);
+            write_path(
+                path_buffer,
+                self.folded_directory(entry.directory),
+                file_name,
+            );
+            return (file_name, path_buffer);
+        }
+
+        // The folded name ends the folded path
+        let directory = &self.directories[entry.directory as usize];
+        folding.fold_into(directory, path_buffer);
+        if !directory.ends_with(MAIN_SEPARATOR) {
+            path_buffer.push(MAIN_SEPARATOR);
+        }
+        let name_start = path_buffer.len();
+        folding.fold_into(self.name_of(entry), path_buffer);
+        (&path_buffer[name_start..], path_buffer)
+    }
+
+    /// Returns true if the path is indexed
+    pub fn contains(&self, file_path: &str) -> bool {
+        split_path(file_path)
+            .is_some_and(|(directory, file_name)| self.find(&directory, &file_name).is_some())
+    }
+
+    /// Exact path of a slot that isn't valid Unicode
+    pub fn raw_path(&self, slot: u32) -> Option<&[u8]> {
+        self.raw_paths.get(&slot).map(|raw_path| &raw_path[..])
+    }
+
+    /// Returns the slots of the live entries grouped by directory, with the directories
+    /// sorted by path and the entries by name, so neighbouring paths share long prefixes
+    pub fn slots_by_path(&self) -> Vec<u32> {
+        let mut directory_ids: Vec<usize> = (0..self.directories.len())
+            .filter(|id| !self.directory_entries[*id].is_empty())
+            .collect();
+        directory_ids.sort_unstable_by_key(|id| &self.directories[*id]);
+
+        let mut slots = Vec::with_capacity(self.len);
+        for id in directory_ids {
+            let start = slots.len();
+            slots.extend_from_slice(&self.directory_entries[id]);
+            slots[start..].sort_unstable_by_key(|slot| self.name_of(&self.entries[*slot as usize]));
+        }
+        slots
+    }
+
+    /// Returns the full details of an indexed path
+    pub fn get(&self, file_path: &str) -> Option<FileDetails> {
+        let (directory, file_name) = split_path(file_path)?;
+        let slot = self.find(&directory, &file_name)?;
+        Some(self.details_at(slot))
+    }
+
+    /// Returns the exact path of every indexed directory
+    pub fn directory_paths(&self) -> Vec<PathBuf> {
+        let mut path_buffer = String::new();
+        (0..self.entries.len() as u32)
+            .filter(|slot| {
+                let entry = &self.entries[*slot as usize];
+                !entry.removed && FILE_TYPES[entry.file_type as usize] == "directory"
+            })
+            .map(|slot| match self.raw_path(slot) {
+                Some(raw_path) => raw_path::from_bytes(raw_path),
+                None => PathBuf::from(
+                    self.view(&self.entries[slot as usize], &mut path_buffer)
+                        .file_path,
+                ),
+            })
+            .collect()
+    }
+
+    /// Returns the full details of every live entry below a directory
+    pub fn subtree_details(&self, directory_path: &str) -> Vec<FileDetails> {
+        let Some(directory_id) = self.directory_ids.get(directory_path).copied() else {
+            return Vec::new();
+        };
+        self.subtree(vec![directory_id])
+            .into_iter()
+            .flat_map(|id| self.directory_entries[id as usize].iter().copied())
+            .filter(|slot| !self.entries[*slot as usize].removed)
+            .map(|slot| self.details_at(slot))
+            .collect()
+    }
+
+    /// Returns the full details of every live entry
+    pub fn details(&self) -> impl Iterator<Item = FileDetails> + '_ {
+        (0..self.entries.len() as u32)
+            .filter(|slot| !self.entries[*slot as usize].removed)
+            .map(|slot| self.details_at(slot))
+    }
+
+    /// Returns the full details of a slot, including its raw path
+    fn details_at(&self, slot: u32) -> FileDetails {
+        let mut path_buffer = String::new();
+        let mut details = self
+            .view(&self.entries[slot as usize], &mut path_buffer)
+            .to_details();
+        details.raw_path = self.raw_paths.get(&slot).map(|raw_path| raw_path.to_vec());
+        details
+    }
+}
+
+impl FileView<'_> {
+    /// Copies the view into FileDetails, without the raw path that only the index has
+    pub fn to_details(self) -> FileDetails {
+        FileDetails {
+            file_name: self.file_name.to_string(),
+            file_path: self.file_path.to_string(),
+            file_size: self.file_size,
+            file_type: self.file_type.to_string(),
+            creation_date: self.creation_date,
+            modified_date: self.modified_date,
+            file_extension: self.file_extension.to_string(),
+            ignored: self.ignored,
+            scanned: self.scanned,
+            raw_path: None,
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//// Exclusion rules for indexing
+// Decides which paths index_files and the watcher leave out of the index.
+// The rules are saved in the setup file and compiled once per indexing run.
+
+//// Imports
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+//// Constants
+const DEFAULT_MAX_DEPTH: usize = 20;
+const DEFAULT_PATTERNS: [&str; 6] = [
+    "node_modules",
+    "target",
+    ".git",
+    "__pycache__",
+    ".cache",
+    ".Trash",
+];
+#[cfg(target_os = "macos")]
+const DEFAULT_PLATFORM_PATTERNS: [&str; 1] = ["Library"];
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_PLATFORM_PATTERNS: [&str; 0] = [];
+#[cfg(target_os = "linux")]
+const DEFAULT_PATHS: [&str; 4] = ["/proc", "/sys", "/dev", "/run"];
+#[cfg(not(target_os = "linux"))]
+const DEFAULT_PATHS: [&str; 0] = [];
+
+//// Data Structures
+/// Exclusion rules as saved in the setup file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ExclusionRules {
+    pub patterns: Vec<String>, // Globs, matched against the name if they contain no separator
+    pub paths: Vec<String>,    // Skipped together with everything below them
+    pub skip_hidden: bool,     // Skip names starting with a dot
+    pub max_file_size: Option<u64>, // Larger files are left out, in bytes
+    pub max_depth: usize,      // Directories deeper below the root are not read
+    pub respect_ignore_files: bool, // Flag entries matched by .gitignore, .ignore and .rfignore
+}
+
+/// Exclusion rules ready for matching
+pub struct CompiledRules {
+    name_globs: GlobSet,
+    path_globs: GlobSet,
+    paths: Vec<PathBuf>,
+    skip_hidden: bool,
+    max_file_size: Option<u64>,
+    max_depth: usize,
+    respect_ignore_files: bool,
+}
+
+impl Default for ExclusionRules {
+    fn default() -> Self {
+        ExclusionRules {
+            patterns: DEFAULT_PATTERNS
+                .iter()
+                .chain(DEFAULT_PLATFORM_PATTERNS.iter())
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            paths: DEFAULT_PATHS.iter().map(|path| path.to_string()).collect(),
+            skip_hidden: false,
+            max_file_size: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+            respect_ignore_files: false,
+        }
+    }
+}
+
+//// Matching
+impl ExclusionRules {
+    /// Checksum of the rules, stored with the index to tell whether it was built with
+    /// the same rules
+    pub fn fingerprint(&self) -> u32 {
+        crc32fast::hash(&serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    /// Compiles the glob patterns, failing with the first invalid pattern
+    pub fn compile(&self) -> Result<CompiledRules, String> {
+        let mut name_globs = GlobSetBuilder::new();
+        let mut path_globs = GlobSetBuilder::new();
+
+        for pattern in &self.patterns {
+            let glob = GlobBuilder::new(pattern)
+                .case_insensitive(cfg!(any(windows, target_os = "macos")))
+                .build()
+                .map_err(|e| format!("Invalid exclusion pattern \"{}\": {}", pattern, e))?;
+            if pattern.contains('/') || pattern.contains('\\') {
+                path_globs.add(glob);
+            } else {
+                name_globs.add(glob);
+            }
+        }
+
+        Ok(CompiledRules {
+            name_globs: name_globs
+                .build()
+                .map_err(|e| format!("Failed to build exclusion patterns: {}", e))?,
+            path_globs: path_globs
+                .build()
+                .map_err(|e| format!("Failed to build exclusion patterns: {}", e))?,
+            paths: self.paths.iter().map(PathBuf::from).collect(),
+            skip_hidden: self.skip_hidden,
+            max_file_size: self.max_file_size,
+            max_depth: self.max_depth,
+            respect_ignore_files: self.respect_ignore_files,
+        })
+    }
+}
+
+impl CompiledRules {
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn respect_ignore_files(&self) -> bool {
+        self.respect_ignore_files
+    }
+
+    /// Returns true if the entry itself should be left out of the index
+    pub fn excludes(&self, path: &Path, is_dir: bool, file_size: u64) -> bool {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        let too_large = !is_dir && self.max_file_size.is_some_and(|limit| file_size > limit);
+
+        (self.skip_hidden && hidden)
+            || too_large
+            || self.paths.iter().any(|excluded| path == excluded)
+            || path
+                .file_name()
+                .is_some_and(|name| self.name_globs.is_match(name))
+            || self.path_globs.is_match(path)
+    }
+
+    /// Returns true if a directory between the root and the path is excluded,
+    /// or if the path lies deeper than index_files would go
+    pub fn excludes_ancestors(&self, root_folder: &Path, path: &Path) -> bool {
+        let Ok(relative_path) = path.strip_prefix(root_folder) else {
+            return true; // Outside of the root folder
+        };
+        if relative_path.components().count() > self.max_depth + 1 {
+            return true;
+        }
+
+        path.ancestors()
+            .skip(1)
+            .take_while(|ancestor| *ancestor != root_folder)
+            .any(|ancestor| self.excludes(ancestor, true, 0))
+    }
+}
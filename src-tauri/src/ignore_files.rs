@@ -0,0 +1,100 @@
+//// Ignore files
+// Reads .gitignore, .ignore and .rfignore files while indexing. Like ripgrep and fd,
+// the files in deeper directories take precedence over the ones above them.
+// Ignored entries are still indexed, but flagged so search can hide them.
+
+//// Imports
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::path::Path;
+use std::sync::Arc;
+
+//// Constants
+// Within a directory, patterns in later files override the earlier ones
+pub const IGNORE_FILES: [&str; 3] = [".gitignore", ".ignore", ".rfignore"];
+
+//// Data Structures
+/// Ignore patterns in effect for a directory, linked to the ones of its parent
+#[derive(Default)]
+pub struct IgnoreRules {
+    parent: Option<Arc<IgnoreRules>>,
+    matcher: Option<Gitignore>, // Patterns from the ignore files of this directory
+}
+
+//// Loading and Matching
+impl IgnoreRules {
+    /// Returns the rules for a directory, stacking its ignore files on top of the parent rules
+    pub fn for_directory(parent: &Arc<IgnoreRules>, directory: &Path) -> Arc<IgnoreRules> {
+        let mut builder = GitignoreBuilder::new(directory);
+        let mut found = false;
+        for name in IGNORE_FILES {
+            let ignore_file = directory.join(name);
+            if !ignore_file.is_file() {
+                continue;
+            }
+            if let Some(e) = builder.add(&ignore_file) {
+                println!("Error reading {}: {}", ignore_file.display(), e);
+            }
+            found = true;
+        }
+        if !found {
+            return parent.clone();
+        }
+
+        match builder.build() {
+            Ok(matcher) => Arc::new(IgnoreRules {
+                parent: Some(parent.clone()),
+                matcher: Some(matcher),
+            }),
+            Err(e) => {
+                println!(
+                    "Error parsing ignore files in {}: {}",
+                    directory.display(),
+                    e
+                );
+                parent.clone()
+            }
+        }
+    }
+
+    /// Loads the rules for the entries of a directory below the root folder by reading every
+    /// ignore file on the way down. Also returns whether the directory itself is ignored.
+    pub fn for_path(root_folder: &Path, directory: &Path) -> (Arc<IgnoreRules>, bool) {
+        let mut rules = Arc::new(IgnoreRules::default());
+        let Ok(relative_path) = directory.strip_prefix(root_folder) else {
+            return (rules, false);
+        };
+
+        let mut current = root_folder.to_path_buf();
+        let mut ignored = false;
+        rules = IgnoreRules::for_directory(&rules, &current);
+        for component in relative_path.components() {
+            current.push(component);
+            ignored = ignored || rules.is_ignored(&current, true);
+            rules = IgnoreRules::for_directory(&rules, &current);
+        }
+        (rules, ignored)
+    }
+
+    /// Returns true if the closest pattern matching the path ignores it
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut rules = Some(self);
+        while let Some(current) = rules {
+            if let Some(matcher) = &current.matcher {
+                match matcher.matched(path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+            rules = current.parent.as_deref();
+        }
+        false
+    }
+}
+
+/// Returns true if the path is one of the ignore files
+pub fn is_ignore_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| IGNORE_FILES.iter().any(|ignore_file| name == *ignore_file))
+}
@@ -0,0 +1,511 @@
+//// Binary index file
+// On-disk format of the file index, replacing the pretty printed JSON of older versions.
+// All numbers are little endian.
+//
+//   Header      magic, version, checksum, entry count and the offset of every section
+//   Strings     interned extensions, each a u32 length followed by the bytes, then the
+//               roots, each a u32 length, the path and the fingerprint of its exclusion rules
+//   Paths       paths grouped by directory, each stored as the length of the prefix it
+//               shares with the previous path and the remaining suffix (both varints),
+//               then the suffix bytes
+//   Records     one fixed-width record per path, in the same order
+//
+// Paths are UTF-8, except for records flagged FLAG_RAW_PATH whose path holds the exact
+// bytes of a path that isn't valid Unicode, as produced by raw_path.
+//
+// The checksum is a CRC32 of the whole file except the checksum itself. Files are
+// replaced through storage::write_atomic, never modified in place, so the memory map
+// of a loaded index never sees a partial write.
+
+//// Imports
+use crate::compact_index::{pack_date, pack_file_type, unpack_date, CompactIndex, FILE_TYPES};
+use crate::raw_path;
+use crate::storage::write_atomic;
+use crate::FileDetails;
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+//// Constants
+const MAGIC: &[u8; 8] = b"RRFINDEX";
+const VERSION: u32 = 4; // Files of other versions are rebuilt by the next indexing run
+const CHECKSUM_OFFSET: usize = 12;
+const HEADER_SIZE: usize = 48;
+const RECORD_SIZE: usize = 32;
+const FLAG_IGNORED: u8 = 1;
+const FLAG_RAW_PATH: u8 = 2;
+const FLAG_SCANNED: u8 = 4;
+
+//// Data Structures
+/// Fixed-size start of the file
+struct Header {
+    version: u32,
+    checksum: u32,
+    entry_count: u64,
+    strings_offset: u64,
+    paths_offset: u64,
+    records_offset: u64,
+}
+
+/// What for_each_entry read besides the entries
+pub struct IndexSummary {
+    pub entry_count: usize,
+    pub rule_fingerprints: HashMap<String, u32>,
+}
+
+/// Reads values from a byte slice, failing instead of panicking on truncated data
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+//// Encoding
+fn push_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&bytes[..CHECKSUM_OFFSET]);
+    hasher.update(&bytes[CHECKSUM_OFFSET + 4..]);
+    hasher.finalize()
+}
+
+fn shared_prefix_len(previous: &[u8], current: &[u8]) -> usize {
+    previous
+        .iter()
+        .zip(current)
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// Serializes the index, with the entries grouped by directory and sorted by path
+pub fn encode_index(index: &CompactIndex) -> Vec<u8> {
+    let slots = index.slots_by_path();
+    let mut extensions: Vec<String> = Vec::new();
+    let mut extension_ids: HashMap<String, u32> = HashMap::new();
+    let mut paths = Vec::new();
+    let mut records = Vec::with_capacity(slots.len() * RECORD_SIZE);
+    let mut previous: Vec<u8> = Vec::new();
+    let mut path_buffer = String::new();
+
+    for slot in &slots {
+        let raw_path = index.raw_path(*slot);
+        let details = index.view(&index.entries()[*slot as usize], &mut path_buffer);
+
+        // The bytes stored for a path, the exact path when it isn't valid Unicode
+        let current = raw_path.unwrap_or(details.file_path.as_bytes());
+        let shared = shared_prefix_len(&previous, current);
+        push_varint(&mut paths, shared as u64);
+        push_varint(&mut paths, (current.len() - shared) as u64);
+        paths.extend_from_slice(&current[shared..]);
+        previous.truncate(shared);
+        previous.extend_from_slice(&current[shared..]);
+
+        // Intern the extension
+        let extension_id = match extension_ids.get(details.file_extension) {
+            Some(id) => *id,
+            None => {
+                let id = extensions.len() as u32;
+                extensions.push(details.file_extension.to_string());
+                extension_ids.insert(details.file_extension.to_string(), id);
+                id
+            }
+        };
+
+        records.extend_from_slice(&details.file_size.to_le_bytes());
+        records.extend_from_slice(&pack_date(details.creation_date).to_le_bytes());
+        records.extend_from_slice(&pack_date(details.modified_date).to_le_bytes());
+        records.extend_from_slice(&extension_id.to_le_bytes());
+        records.push(pack_file_type(details.file_type));
+        let mut flags = 0;
+        if details.ignored {
+            flags |= FLAG_IGNORED;
+        }
+        if raw_path.is_some() {
+            flags |= FLAG_RAW_PATH;
+        }
+        if details.scanned {
+            flags |= FLAG_SCANNED;
+        }
+        records.push(flags);
+        records.extend_from_slice(&[0, 0]); // Reserved
+    }
+
+    let mut strings = Vec::new();
+    strings.extend_from_slice(&(extensions.len() as u32).to_le_bytes());
+    for extension in &extensions {
+        strings.extend_from_slice(&(extension.len() as u32).to_le_bytes());
+        strings.extend_from_slice(extension.as_bytes());
+    }
+    let rule_fingerprints = index.rule_fingerprints();
+    strings.extend_from_slice(&(rule_fingerprints.len() as u32).to_le_bytes());
+    for (root_path, fingerprint) in rule_fingerprints {
+        strings.extend_from_slice(&(root_path.len() as u32).to_le_bytes());
+        strings.extend_from_slice(root_path.as_bytes());
+        strings.extend_from_slice(&fingerprint.to_le_bytes());
+    }
+
+    let strings_offset = HEADER_SIZE;
+    let paths_offset = strings_offset + strings.len();
+    let records_offset = paths_offset + paths.len();
+
+    let mut bytes = Vec::with_capacity(records_offset + records.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes()); // Checksum, filled in below
+    bytes.extend_from_slice(&(slots.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&(strings_offset as u64).to_le_bytes());
+    bytes.extend_from_slice(&(paths_offset as u64).to_le_bytes());
+    bytes.extend_from_slice(&(records_offset as u64).to_le_bytes());
+    bytes.extend_from_slice(&strings);
+    bytes.extend_from_slice(&paths);
+    bytes.extend_from_slice(&records);
+
+    let checksum = checksum(&bytes);
+    bytes[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+/// Writes an encoded index, keeping the previous file as the backup if it is intact
+pub async fn write_index(encoded: &[u8], index_path: &Path) -> Result<(), String> {
+    write_atomic(index_path, encoded, is_valid).await
+}
+
+//// Decoding
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], position: usize) -> Self {
+        Reader { bytes, position }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("Index file is truncated")?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Index file contains an invalid number".to_string())
+    }
+}
+
+fn read_header(bytes: &[u8]) -> Result<Header, String> {
+    let mut reader = Reader::new(bytes, 0);
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err("Not an index file".to_string());
+    }
+    let header = Header {
+        version: reader.u32()?,
+        checksum: reader.u32()?,
+        entry_count: reader.u64()?,
+        strings_offset: reader.u64()?,
+        paths_offset: reader.u64()?,
+        records_offset: reader.u64()?,
+    };
+    if header.version != VERSION {
+        return Err(format!("Unsupported index version {}", header.version));
+    }
+    if checksum(bytes) != header.checksum {
+        return Err("Index file is damaged, its checksum doesn't match".to_string());
+    }
+    Ok(header)
+}
+
+/// Returns true if the file is an index written in an older format version
+pub fn is_outdated(index_path: &Path) -> bool {
+    let mut start = [0; MAGIC.len() + 4];
+    let read = File::open(index_path).and_then(|mut file| file.read_exact(&mut start));
+    read.is_ok()
+        && start.starts_with(MAGIC)
+        && u32::from_le_bytes(start[MAGIC.len()..].try_into().unwrap()) < VERSION
+}
+
+/// Returns true if the file is an index of the current version with a matching checksum
+pub fn is_valid(index_path: &Path) -> bool {
+    let Ok(file) = File::open(index_path) else {
+        return false;
+    };
+    // Safety: see for_each_entry
+    unsafe { Mmap::map(&file) }.is_ok_and(|bytes| read_header(&bytes).is_ok())
+}
+
+/// Maps the index file into memory and calls the callback with every entry.
+/// The same FileDetails is reused for every call to avoid an allocation per entry.
+/// Returns the number of entries along with the fingerprints of the rules of every root.
+pub fn for_each_entry(
+    index_path: &Path,
+    mut callback: impl FnMut(&FileDetails),
+) -> Result<IndexSummary, String> {
+    let file = File::open(index_path).map_err(|e| format!("Failed to read index file: {}", e))?;
+    // Safety: index files are only ever replaced by renaming a new file over them,
+    // never modified in place, so the mapped bytes can't change while they are read
+    let bytes =
+        unsafe { Mmap::map(&file) }.map_err(|e| format!("Failed to map index file: {}", e))?;
+
+    let header = read_header(&bytes)?;
+    let as_offset =
+        |offset: u64| usize::try_from(offset).map_err(|_| "Index file is too large".to_string());
+
+    let mut strings = Reader::new(&bytes, as_offset(header.strings_offset)?);
+    let extension_count = strings.u32()?;
+    let mut extensions = Vec::new();
+    for _ in 0..extension_count {
+        let len = strings.u32()? as usize;
+        let extension = std::str::from_utf8(strings.take(len)?)
+            .map_err(|_| "Index file contains an invalid extension")?;
+        extensions.push(extension);
+    }
+    let mut rule_fingerprints = HashMap::new();
+    for _ in 0..strings.u32()? {
+        let len = strings.u32()? as usize;
+        let root_path = std::str::from_utf8(strings.take(len)?)
+            .map_err(|_| "Index file contains an invalid root")?;
+        rule_fingerprints.insert(root_path.to_string(), strings.u32()?);
+    }
+
+    let mut paths = Reader::new(&bytes, as_offset(header.paths_offset)?);
+    let mut records = Reader::new(&bytes, as_offset(header.records_offset)?);
+    let mut path_bytes: Vec<u8> = Vec::new();
+    let mut details = FileDetails {
+        file_name: String::new(),
+        file_path: String::new(),
+        file_size: 0,
+        file_type: String::new(),
+        creation_date: None,
+        modified_date: None,
+        file_extension: String::new(),
+        ignored: false,
+        scanned: false,
+        raw_path: None,
+    };
+
+    for _ in 0..header.entry_count {
+        let shared = paths.varint()? as usize;
+        let suffix_len = paths.varint()? as usize;
+        if shared > path_bytes.len() {
+            return Err("Index file contains an invalid path".to_string());
+        }
+        path_bytes.truncate(shared);
+        path_bytes.extend_from_slice(paths.take(suffix_len)?);
+
+        details.file_size = records.u64()?;
+        details.creation_date = unpack_date(records.u64()?);
+        details.modified_date = unpack_date(records.u64()?);
+        let extension = extensions
+            .get(records.u32()? as usize)
+            .ok_or("Index file contains an invalid extension")?;
+        let file_type = FILE_TYPES
+            .get(records.u8()? as usize)
+            .ok_or("Index file contains an invalid file type")?;
+        let flags = records.u8()?;
+        details.ignored = flags & FLAG_IGNORED != 0;
+        details.scanned = flags & FLAG_SCANNED != 0;
+        records.take(2)?; // Reserved
+
+        details.file_path.clear();
+        if flags & FLAG_RAW_PATH != 0 {
+            details
+                .file_path
+                .push_str(&raw_path::display(&raw_path::from_bytes(&path_bytes)));
+            details.raw_path = Some(path_bytes.clone());
+        } else {
+            let file_path = std::str::from_utf8(&path_bytes)
+                .map_err(|_| "Index file contains an invalid path".to_string())?;
+            details.file_path.push_str(file_path);
+            details.raw_path = None;
+        }
+        details.file_name.clear();
+        if let Some(file_name) = Path::new(&details.file_path).file_name() {
+            details.file_name.push_str(&file_name.to_string_lossy());
+        }
+        details.file_extension.clear();
+        details.file_extension.push_str(extension);
+        details.file_type.clear();
+        details.file_type.push_str(file_type);
+        callback(&details);
+    }
+
+    Ok(IndexSummary {
+        entry_count: header.entry_count as usize,
+        rule_fingerprints,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn details(file_path: &str, file_type: &str) -> FileDetails {
+        FileDetails {
+            file_name: Path::new(file_path)
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+            file_path: file_path.to_string(),
+            file_size: 42,
+            file_type: file_type.to_string(),
+            creation_date: None,
+            modified_date: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            file_extension: "txt".to_string(),
+            ignored: false,
+            scanned: false,
+            raw_path: None,
+        }
+    }
+
+    fn sample_index() -> CompactIndex {
+        let mut index = CompactIndex::default();
+        let mut directory = details("/root/docs", "directory");
+        directory.file_extension = String::new();
+        directory.scanned = true;
+        index.insert(&directory);
+        let mut ignored = details("/root/docs/notes.txt", "file");
+        ignored.ignored = true;
+        index.insert(&ignored);
+        index.insert(&details("/root/a.txt", "file"));
+        let mut raw = details("/root/caf\u{FFFD}E9.txt", "file");
+        raw.raw_path = Some(b"/root/caf\xE9.txt".to_vec());
+        index.insert(&raw);
+        index.set_rule_fingerprints(HashMap::from([("/root".to_string(), 7)]));
+        index
+    }
+
+    /// Writes the bytes to a file of its own, so tests can run in parallel
+    fn write_temp(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.bin", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn entries_survive_a_round_trip() {
+        let index = sample_index();
+        let path = write_temp("round-trip", &encode_index(&index));
+
+        let mut decoded = Vec::new();
+        let summary = for_each_entry(&path, |details| decoded.push(details.clone())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(summary.entry_count, 4);
+        assert_eq!(summary.rule_fingerprints, *index.rule_fingerprints());
+        assert_eq!(decoded.len(), 4);
+        for details in decoded {
+            let expected = index.get(&details.file_path).unwrap();
+            assert_eq!(details.file_name, expected.file_name);
+            assert_eq!(details.file_size, expected.file_size);
+            assert_eq!(details.file_type, expected.file_type);
+            assert_eq!(details.modified_date, expected.modified_date);
+            assert_eq!(details.file_extension, expected.file_extension);
+            assert_eq!(details.ignored, expected.ignored);
+            assert_eq!(details.scanned, expected.scanned);
+            assert_eq!(details.raw_path, expected.raw_path);
+        }
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let bytes = encode_index(&sample_index());
+        for len in [0, HEADER_SIZE / 2, HEADER_SIZE, bytes.len() - RECORD_SIZE] {
+            let mut truncated = bytes[..len].to_vec();
+            // A matching checksum, so the sections themselves are found to be cut short
+            if len >= HEADER_SIZE {
+                let checksum = checksum(&truncated);
+                truncated[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4]
+                    .copy_from_slice(&checksum.to_le_bytes());
+            }
+            let path = write_temp(&format!("truncated-{}", len), &truncated);
+            let result = for_each_entry(&path, |_| {});
+            std::fs::remove_file(&path).unwrap();
+            assert!(
+                result
+                    .err()
+                    .is_some_and(|message| message.contains("truncated")),
+                "{} bytes were accepted",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn checksum_mismatches_are_rejected() {
+        let mut bytes = encode_index(&sample_index());
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        let path = write_temp("checksum", &bytes);
+        let result = for_each_entry(&path, |_| {});
+        std::fs::remove_file(&path).unwrap();
+        assert!(result
+            .err()
+            .is_some_and(|message| message.contains("checksum")));
+    }
+
+    #[test]
+    fn older_versions_are_outdated_rather_than_damaged() {
+        let mut bytes = encode_index(&sample_index());
+        let current = write_temp("current-version", &bytes);
+        bytes[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(VERSION - 1).to_le_bytes());
+        let older = write_temp("older-version", &bytes);
+        let outdated = (is_outdated(&current), is_outdated(&older));
+        std::fs::remove_file(&current).unwrap();
+        std::fs::remove_file(&older).unwrap();
+        assert_eq!(outdated, (false, true));
+    }
+
+    #[tokio::test]
+    async fn damaged_files_never_replace_the_backup() {
+        let directory = std::env::temp_dir().join(format!("backup-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("index.bin");
+        let empty = encode_index(&CompactIndex::default());
+        let sample = encode_index(&sample_index());
+
+        write_index(&empty, &path).await.unwrap();
+        write_index(&sample, &path).await.unwrap();
+        assert_eq!(std::fs::read(storage::backup_path(&path)).unwrap(), empty);
+
+        std::fs::write(&path, &sample[..HEADER_SIZE]).unwrap();
+        assert!(!is_valid(&path));
+        write_index(&sample, &path).await.unwrap();
+        let backup = std::fs::read(storage::backup_path(&path)).unwrap();
+        let leftovers = std::fs::read_dir(&directory).unwrap().count();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(backup, empty);
+        assert_eq!(leftovers, 2, "temporary files were left behind");
+    }
+}
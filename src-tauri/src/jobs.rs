@@ -0,0 +1,193 @@
+//// Index job manager
+// Owns the task started by create_index, so there is only ever one indexing run. A new
+// run supersedes the running one by cancelling it and waiting until it has saved what it
+// found. Pausing stops the walk the same way but keeps the directories that were not
+// scanned yet in a checkpoint file, and the next run continues from that queue, also
+// after a restart.
+
+//// Imports
+use crate::storage;
+use crate::walker::QueuedDirectory;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+use tauri::api::path::config_dir;
+use tauri::async_runtime::JoinHandle;
+use tauri::Window;
+use tokio::sync::Mutex;
+
+//// Constants
+pub const CHECKPOINT_FILE: &str = "index_checkpoint.json";
+const RUNNING: u8 = 0;
+const PAUSE: u8 = 1;
+const CANCEL: u8 = 2;
+
+//// Global Variables
+// The running indexing task. Held while a run is started so runs can't start concurrently.
+pub static JOB: Lazy<Mutex<Option<Job>>> = Lazy::new(|| Mutex::new(None));
+
+//// Data Structures
+/// Why a run was asked to stop early
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Pause,
+    Cancel,
+}
+
+/// Shared between the job manager, the indexing task and its walker threads
+#[derive(Default)]
+pub struct JobControl {
+    stop: AtomicU8,
+    finished: AtomicBool, // Set by the task once it has saved its results
+}
+
+/// Handle of the indexing task
+pub struct Job {
+    handle: JoinHandle<()>,
+    control: Arc<JobControl>,
+}
+
+/// What the UI sees of the indexing job
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Idle,
+    Running,
+    Paused, // Stopped with a checkpoint to continue from
+}
+
+/// Progress of a paused run, saved so it can continue after a restart
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Checkpoint {
+    pub completed_roots: Vec<String>,  // Roots that were fully scanned
+    pub pending: Vec<QueuedDirectory>, // Directories of the other roots that were not scanned yet
+}
+
+//// Job Control
+impl JobControl {
+    pub fn request_stop(&self, reason: StopReason) {
+        let value = match reason {
+            StopReason::Pause => PAUSE,
+            StopReason::Cancel => CANCEL,
+        };
+        // Cancelling wins over pausing
+        self.stop.fetch_max(value, Ordering::SeqCst);
+    }
+
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        match self.stop.load(Ordering::SeqCst) {
+            PAUSE => Some(StopReason::Pause),
+            CANCEL => Some(StopReason::Cancel),
+            _ => None,
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed) != RUNNING
+    }
+
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Job {
+    pub fn new(handle: JoinHandle<()>, control: Arc<JobControl>) -> Self {
+        Job { handle, control }
+    }
+}
+
+/// Stops the job in the slot, if any, and waits until it has saved its results.
+/// Returns true if the job was still running.
+pub async fn stop(job: &mut Option<Job>, reason: StopReason) -> bool {
+    let Some(job) = job.take() else {
+        return false;
+    };
+    let running = !job.control.finished.load(Ordering::SeqCst);
+    job.control.request_stop(reason);
+    // The task ends on its own once the walk has stopped, a panic has been logged already
+    let _ = job.handle.await;
+    running
+}
+
+/// Pauses the running job before the app exits, so the next start continues from its queue
+pub async fn pause_for_exit() {
+    let mut job = JOB.lock().await;
+    if stop(&mut job, StopReason::Pause).await {
+        println!("Paused indexing before exiting.");
+    }
+}
+
+/// Returns the state of the indexing job
+pub async fn state() -> JobState {
+    let running = JOB
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(|job| !job.control.finished.load(Ordering::SeqCst));
+    if running {
+        JobState::Running
+    } else if has_checkpoint() {
+        JobState::Paused
+    } else {
+        JobState::Idle
+    }
+}
+
+//// Checkpoint
+fn checkpoint_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(CHECKPOINT_FILE))
+}
+
+pub fn has_checkpoint() -> bool {
+    checkpoint_path().is_some_and(|path| storage::exists(&path))
+}
+
+/// Loads the checkpoint of a paused run, reporting a damaged file to the UI
+pub fn load_checkpoint(window: &Window) -> Checkpoint {
+    let Some(path) = checkpoint_path().filter(|path| storage::exists(path)) else {
+        return Checkpoint::default();
+    };
+    match storage::load_with_backup(&path, storage::read_json) {
+        Ok((checkpoint, issue)) => {
+            if let Some(issue) = issue {
+                storage::report_issue(window, &issue);
+            }
+            checkpoint
+        }
+        Err(issue) => {
+            // Without the queue the next run simply scans everything again
+            storage::report_issue(window, &issue);
+            Checkpoint::default()
+        }
+    }
+}
+
+pub async fn save_checkpoint(checkpoint: &Checkpoint) -> Result<(), String> {
+    let path = checkpoint_path().ok_or("Failed to retrieve config directory")?;
+    storage::write_json(&path, checkpoint).await
+}
+
+/// Removes the checkpoint and its backup, so the next run starts from the top
+pub async fn clear_checkpoint() {
+    let Some(path) = checkpoint_path() else {
+        return;
+    };
+    for path in [storage::backup_path(&path), path] {
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                println!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Returns true if the paused run had already finished the root
+pub fn is_completed(checkpoint: &Checkpoint, root_path: &Path) -> bool {
+    checkpoint
+        .completed_roots
+        .iter()
+        .any(|completed| Path::new(completed) == root_path)
+}
@@ -0,0 +1,48 @@
+//// Search and index
+// The index and the search over it, kept free of Tauri so the app and the benchmarks
+// build the same code.
+
+//// Modules
+pub mod compact_index;
+pub mod normalize;
+pub mod query;
+pub mod raw_path;
+pub mod search;
+pub mod trigram_index;
+
+//// Imports
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+//// Data Structures
+/// Data structure to hold the index
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileDetails {
+    #[serde(default)]
+    pub file_name: String,
+    pub file_path: String,
+    pub file_size: u64,
+    pub file_type: String,
+    pub creation_date: Option<SystemTime>,
+    #[serde(default)]
+    pub modified_date: Option<SystemTime>,
+    pub file_extension: String,
+    #[serde(default)]
+    pub ignored: bool, // Matched by an ignore file, hidden from search unless requested
+    #[serde(default)]
+    pub scanned: bool, // Directory whose entries were read, so its modification time can be trusted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_path: Option<Vec<u8>>, // Exact path when file_path is an escaped display path
+}
+
+impl FileDetails {
+    /// The exact path on disk, which file_path is only the display form of for
+    /// names that aren't valid Unicode
+    pub fn path(&self) -> PathBuf {
+        match &self.raw_path {
+            Some(bytes) => raw_path::from_bytes(bytes),
+            None => PathBuf::from(&self.file_path),
+        }
+    }
+}
@@ -1,56 +1,149 @@
-// TODO:
-// - Fix Indexation Time
-// - Add The Scoring system for favorite extension types
+//// Modules
+mod exclusions;
+mod ignore_files;
+mod index_file;
+mod jobs;
+mod progress;
+mod roots;
+mod storage;
+mod usage;
+mod walker;
+mod watcher;
 
 //// Imports
+use compact_index::CompactIndex;
 use dirs::{audio_dir, desktop_dir, document_dir, download_dir, picture_dir, video_dir};
-use num_cpus;
+use exclusions::{CompiledRules, ExclusionRules};
+use ignore_files::IgnoreRules;
+use jobs::{Checkpoint, Job, JobControl, JobState, StopReason};
+use normalize::Folding;
 use once_cell::sync::Lazy;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use progress::{IndexStatus, PROGRESS};
+use query::{parse_query, QueryDiagnostic, SearchQuery};
+use roots::{replace_setup_root, root_for_path, upsert_root, validate_root, RootConfig};
+use rust_finder_lib::{compact_index, normalize, query, raw_path, search, FileDetails};
+use search::{
+    extension_weight, find_matches, match_ranges, set_search_threads, sort_matches, top_matches,
+    MatchRanges, Ranking, SearchToken, SortBy,
+};
 use serde::{Deserialize, Serialize};
 use serde_json;
 use serde_json::json;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet};
 use std::env::consts::OS as OS_TYPE;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
-use std::time::{Instant, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use tauri::api::path::config_dir;
 use tauri::{Manager, Window};
 use tokio::fs;
-use tokio::io::AsyncReadExt;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard};
+use walker::{PendingDirectory, QueuedDirectory, Walk, WalkBatch};
 
 //// Constants
-const MINIMUM_SCORE: i16 = 20;
-const SKIP_DIRECTORY: &str = "Library"; // Directory to skip
 const OS: &str = OS_TYPE;
-const DEPTH_STOP: usize = 20;
-const FILE_INDEX: &str = "file_index.json";
+const FILE_INDEX: &str = "file_index.bin";
 const SETUP_FILE: &str = "setup_file.json";
-const EXTENSIONS_INDEX: &str = "extensions_index.json";
+const RECENT_FILES: &str = "recent_files.json";
+const EXTENSIONS_INDEX: &str = "extensions_index.bin";
+const LEGACY_FILE_INDEX: &str = "file_index.json"; // Migrated to FILE_INDEX on startup
+const LEGACY_EXTENSIONS_INDEX: &str = "extensions_index.json";
+const DEFAULT_EXTENSION_WEIGHT: i64 = 30; // Boost for preferred extensions without a weight
+const PREVIEW_LIMIT: usize = 200; // Paths listed by preview_exclusions
+const PUBLISH_BATCH_SIZE: usize = 256; // Most directory batches made searchable under one lock
+const DEFAULT_RESULT_LIMIT: usize = 200; // Results per page when search_files gets no limit
+const FIRST_BATCH_SIZE: usize = 50; // Matches stream_search waits for before its first batch
+const STREAM_INTERVAL: Duration = Duration::from_millis(100); // Least time between batches
 
 //// Global Variables
-static ROOT_FOLDER: Lazy<Mutex<String>> = Lazy::new(|| Mutex::new(String::new()));
-static EXTENSIONS: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(Vec::new()));
-static IN_MEMORY_INDEX: Lazy<Mutex<HashMap<String, String>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
+static ROOTS: Lazy<Mutex<Vec<RootConfig>>> = Lazy::new(|| Mutex::new(Vec::new()));
+// Maps each preferred extension to its ranking boost
+static EXTENSIONS: Lazy<Mutex<HashMap<String, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// Searches share the read lock, indexing and the watcher take the write lock
+static IN_MEMORY_INDEX: Lazy<RwLock<CompactIndex>> =
+    Lazy::new(|| RwLock::new(CompactIndex::default()));
+// Only the files with a preferred extension
+static EXTENSIONS_MEMORY_INDEX: Lazy<RwLock<CompactIndex>> =
+    Lazy::new(|| RwLock::new(CompactIndex::default()));
+// Sorted matches of the last search, so the following pages don't search again
+static LAST_SEARCH: Lazy<Mutex<Option<CachedSearch>>> = Lazy::new(|| Mutex::new(None));
 
 //// Data Structures
-/// Data structure to hold the index
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct FileDetails {
+/// Data structure to hold the index of files
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct FileIndex {
+    files: HashMap<String, FileDetails>, // Maps full file path to file details
+    #[serde(default)]
+    rule_fingerprints: HashMap<String, u32>, // Exclusion rules each root was fully indexed with
+}
+
+/// Error returned by search_files
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum SearchError {
+    InvalidQuery { diagnostics: Vec<QueryDiagnostic> },
+    Cancelled, // A newer search started before this one finished
+    Failed { message: String },
+}
+
+impl From<String> for SearchError {
+    fn from(message: String) -> Self {
+        SearchError::Failed { message }
+    }
+}
+
+/// A single match, with the characters that matched for highlighting
+#[derive(Serialize, Debug, Clone)]
+struct SearchResult {
+    file_name: String,
     file_path: String,
-    file_size: u64,
-    file_type: String,
-    creation_date: Option<SystemTime>,
-    file_extension: String,
+    root: String, // Root the match was found in
+    score: i64,
+    name_ranges: MatchRanges, // Matched characters of file_name
+    path_ranges: MatchRanges, // Matched characters of file_path
 }
 
-/// Data structure to hold the index of files
-#[derive(Serialize, Deserialize, Debug)]
-struct FileIndex {
-    files: HashMap<String, FileDetails>, // Maps filename to file details
+/// Results of search_files
+#[derive(Serialize, Debug)]
+struct SearchResults {
+    results: Vec<SearchResult>, // Matches on the page
+    total: usize,               // Number of matches on all pages
+    partial: bool,              // Indexing is still running or paused, so matches may be missing
+}
+
+/// Ranked results sent by stream_search as "search-results" events
+#[derive(Serialize, Debug, Clone)]
+struct SearchBatch {
+    query_id: u64,
+    results: Vec<SearchResult>, // Best matches found so far
+    total: usize,               // Number of matches found so far
+    partial: bool,
+    done: bool, // Last batch of the query, the scan has finished
+}
+
+/// Everything that decides the order of the matches of a search
+#[derive(Debug, Clone, PartialEq)]
+struct SearchKey {
+    query: String,
+    preferred_only: bool,
+    include_ignored: bool,
+    folding: Folding,
+    sort: SortBy,
+}
+
+/// Matches of a search as score and slot, valid while the index keeps its generation
+struct CachedSearch {
+    key: SearchKey,
+    generation: u64,
+    matches: Vec<(i64, u32)>,
+}
+
+/// Indexed paths that a set of exclusion rules would drop
+#[derive(Serialize, Debug)]
+struct ExclusionPreview {
+    total: usize,       // Number of indexed entries the rules would drop
+    paths: Vec<String>, // First PREVIEW_LIMIT of them, sorted
 }
 
 #[derive(Serialize, Deserialize)]
@@ -60,41 +153,93 @@ struct SetupData {
     recent_files: Vec<(i32, (String, String))>,
 }
 
+impl SearchKey {
+    /// Builds the key of a search from the options of the search commands
+    fn new(
+        query: String,
+        preferred_only: Option<bool>,
+        include_ignored: Option<bool>,
+        case_sensitive: Option<bool>,
+        accent_sensitive: Option<bool>,
+        sort: Option<SortBy>,
+    ) -> Self {
+        SearchKey {
+            query,
+            preferred_only: preferred_only.unwrap_or(false),
+            include_ignored: include_ignored.unwrap_or(false),
+            folding: Folding {
+                case_sensitive: case_sensitive.unwrap_or(false),
+                accent_sensitive: accent_sensitive.unwrap_or(false),
+            },
+            sort: sort.unwrap_or_default(),
+        }
+    }
+}
+
 //// Global Variables Getter and Setters
-// Function to set the value of ROOT_FOLDER
-async fn set_root_folder(root_folder: String) -> Result<(), String> {
-    let mut folder = ROOT_FOLDER.lock().await;
-    *folder = root_folder;
+// Function to set the value of ROOTS
+async fn set_roots(roots: Vec<RootConfig>) -> Result<(), String> {
+    let mut current_roots = ROOTS.lock().await;
+    *current_roots = roots;
     Ok(())
 }
 
 // Helper function to set EXTENSIONS using Lazy<Mutex>
-async fn set_extensions(extensions: Vec<String>) -> Result<(), String> {
+async fn set_extensions(extensions: HashMap<String, i64>) -> Result<(), String> {
     let mut ext = EXTENSIONS.lock().await;
     *ext = extensions;
     Ok(())
 }
 
-// Function to get the current value of ROOT_FOLDER
-async fn get_root_folder() -> Result<String, String> {
-    let folder = ROOT_FOLDER.lock().await;
-    Ok(folder.clone())
+// Function to get the current value of ROOTS
+async fn get_roots() -> Result<Vec<RootConfig>, String> {
+    let roots = ROOTS.lock().await;
+    Ok(roots.clone())
 }
 
 // Function to get the current value of EXTENSIONS
-async fn get_extensions() -> Result<Vec<String>, String> {
+async fn get_extensions() -> Result<HashMap<String, i64>, String> {
     let ext = EXTENSIONS.lock().await;
     Ok(ext.clone())
 }
 
+/// Compiles the exclusion rules of a root, falling back to the defaults if they are invalid
+fn compile_exclusions(root: &RootConfig) -> CompiledRules {
+    root.exclusions.compile().unwrap_or_else(|e| {
+        println!("{}. Using the default exclusion rules.", e);
+        ExclusionRules::default()
+            .compile()
+            .expect("default exclusion rules are valid")
+    })
+}
+
+/// Normalizes the extension list from the setup page and pairs each one with its weight
+fn resolve_extension_weights(
+    extensions: &[String],
+    extension_weights: &HashMap<String, i64>,
+) -> HashMap<String, i64> {
+    extensions
+        .iter()
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .map(|ext| {
+            let weight = extension_weights
+                .get(&ext)
+                .copied()
+                .unwrap_or(DEFAULT_EXTENSION_WEIGHT);
+            (ext, weight)
+        })
+        .collect()
+}
+
 //// Setup Function
 /// Function to check if setup file exists
 async fn setup_file_check() -> Result<bool, String> {
     // Get the path to the setup file
     let path = config_dir().unwrap().join(SETUP_FILE);
 
-    // Check if the file exists
-    let exists = Path::new(&path).exists();
+    // Check if the file or its backup exists
+    let exists = storage::exists(&path);
 
     Ok(exists)
 }
@@ -166,7 +311,14 @@ async fn save_setup_file(
     window: Window,
     root_folder: String,
     extensions: Vec<String>,
+    extension_weights: Option<HashMap<String, i64>>,
+    exclusions: Option<ExclusionRules>,
+    search_threads: Option<usize>,
 ) -> Result<(), String> {
+    // Setup picks the first root and keeps the ones added through add_root
+    let mut roots = get_roots().await?;
+    let replaced = replace_setup_root(&mut roots, root_folder, exclusions)?;
+
     // Get the path to the setup file
     let path: PathBuf = config_dir()
         .ok_or("Failed to retrieve config directory")?
@@ -175,23 +327,47 @@ async fn save_setup_file(
     // Define a default JSON structure for the setup file
     let mut setup = json!({
         "os": detect_os().await.unwrap_or_else(|_| "unknown".to_string()),
-        "root_folder": "",
+        "roots": [],
         "file_extensions": [],
+        "extension_weights": {},
         "key_folders": {}
     });
 
     // If the setup file exists, attempt to read and parse it
-    if let Ok(existing_content) = fs::read_to_string(&path).await {
-        if let Ok(existing_setup) = serde_json::from_str::<serde_json::Value>(&existing_content) {
-            setup = existing_setup;
-        }
+    if let Ok((existing_setup, _)) = storage::load_with_backup(&path, storage::read_json) {
+        setup = existing_setup;
     }
 
-    // Update the root folder and extensions fields
-    set_root_folder(root_folder.clone()).await?;
-    setup["root_folder"] = serde_json::Value::String(root_folder);
+    // Update the roots and extensions fields, dropping the single root of older setup files
+    if let Some(setup) = setup.as_object_mut() {
+        setup.remove("root_folder");
+        setup.remove("exclusions");
+    }
+    setup["roots"] = json!(roots);
+    let new_folder = PathBuf::from(&roots[0].path);
+    set_roots(roots).await?;
+
+    // Hide the entries of a replaced root right away, the reindex drops them from the
+    // saved index. Entries the new root also covers stay for the reindex to reuse.
+    if let Some(replaced) = replaced.filter(|replaced| {
+        !new_folder.starts_with(&replaced.path)
+            && !Path::new(&replaced.path).starts_with(&new_folder)
+    }) {
+        IN_MEMORY_INDEX.write().await.remove_subtree(&replaced.path);
+        EXTENSIONS_MEMORY_INDEX
+            .write()
+            .await
+            .remove_subtree(&replaced.path);
+    }
 
-    set_extensions(extensions.clone()).await?;
+    let extension_weights =
+        resolve_extension_weights(&extensions, &extension_weights.unwrap_or_default());
+    setup["extension_weights"] = json!(extension_weights);
+    set_extensions(extension_weights).await?;
+    if let Some(search_threads) = search_threads {
+        set_search_threads(Some(search_threads))?;
+        setup["search_threads"] = json!(search_threads);
+    }
     setup["file_extensions"] = serde_json::Value::Array(
         extensions
             .into_iter()
@@ -220,46 +396,76 @@ async fn save_setup_file(
             .collect(),
     );
 
-    // Serialize the updated setup structure to JSON
-    let content = serde_json::to_string_pretty(&setup)
-        .map_err(|e| format!("Failed to serialize setup file: {}", e))?;
+    // Write the updated setup structure to the setup file
+    storage::write_json(&path, &setup).await?;
 
-    // Write the updated JSON content to the setup file
-    fs::write(&path, content)
-        .await
-        .map_err(|e| format!("Failed to write setup file: {}", e))?;
+    // Roots finished by a paused run may have new settings, so start over
+    jobs::clear_checkpoint().await;
 
     // Start indexing the files using the root folder
     let config_dir = config_dir().ok_or("Failed to retrieve config directory")?;
     let file_index_path = config_dir.join(FILE_INDEX);
-    create_index(window, file_index_path).await?;
+    create_index(window.clone(), file_index_path).await?;
+    roots::start_scheduler(window);
 
     Ok(())
 }
 
-/// Function to load the setup_file.json
-async fn load_setup() -> Result<HashMap<String, HashMap<String, String>>, String> {
+/// Stores the roots in setup_file.json without touching the other settings
+async fn save_roots(roots: &[RootConfig]) -> Result<(), String> {
+    let path: PathBuf = config_dir()
+        .ok_or("Failed to retrieve config directory")?
+        .join(SETUP_FILE);
+
+    let (mut setup, _): (serde_json::Value, _) =
+        storage::load_with_backup(&path, storage::read_json).map_err(|issue| issue.message)?;
+    if let Some(setup) = setup.as_object_mut() {
+        setup.remove("root_folder");
+        setup.remove("exclusions");
+    }
+    setup["roots"] = json!(roots);
+
+    storage::write_json(&path, &setup).await
+}
+
+/// Function to load the setup_file.json, falling back to its backup if it is damaged
+async fn load_setup(window: &Window) -> Result<HashMap<String, HashMap<String, String>>, String> {
     // Get the path to the setup file
     let path: PathBuf = config_dir()
         .ok_or("Failed to retrieve config directory")?
         .join(SETUP_FILE);
 
-    // Attempt to read the setup file
-    let content = match fs::read_to_string(&path).await {
-        Ok(data) => data,
-        Err(_) => {
-            // If the file doesn't exist, return an empty HashMap
-            return Ok(HashMap::new());
+    // If the file doesn't exist, return an empty HashMap
+    if !storage::exists(&path) {
+        return Ok(HashMap::new());
+    }
+
+    // Read and parse the JSON content
+    let setup: serde_json::Value = match storage::load_with_backup(&path, storage::read_json) {
+        Ok((setup, issue)) => {
+            if let Some(issue) = issue {
+                storage::report_issue(window, &issue);
+            }
+            setup
+        }
+        Err(issue) => {
+            storage::report_issue(window, &issue);
+            return Err(issue.message);
         }
     };
 
-    // Parse the JSON content
-    let setup: serde_json::Value =
-        serde_json::from_str(&content).map_err(|e| format!("Failed to parse setup file: {}", e))?;
-
-    // Load the root folder if available
-    if let Some(root_folder) = setup.get("root_folder").and_then(|v| v.as_str()) {
-        set_root_folder(root_folder.to_string()).await?;
+    // Load the roots, turning the single root folder of older setup files into a root
+    if let Some(roots) = setup
+        .get("roots")
+        .and_then(|v| serde_json::from_value::<Vec<RootConfig>>(v.clone()).ok())
+    {
+        set_roots(roots).await?;
+    } else if let Some(root_folder) = setup.get("root_folder").and_then(|v| v.as_str()) {
+        let exclusions: ExclusionRules = setup
+            .get("exclusions")
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default();
+        set_roots(vec![RootConfig::new(root_folder.to_string(), exclusions)]).await?;
     }
 
     // Load the extensions if available
@@ -268,9 +474,26 @@ async fn load_setup() -> Result<HashMap<String, HashMap<String, String>>, String
             .iter()
             .filter_map(|ext| ext.as_str().map(|s| s.to_string()))
             .collect();
-        set_extensions(extensions_vec).await?;
+
+        // Setup files written before weights existed fall back to the default weight
+        let extension_weights: HashMap<String, i64> = setup
+            .get("extension_weights")
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default();
+        set_extensions(resolve_extension_weights(
+            &extensions_vec,
+            &extension_weights,
+        ))
+        .await?;
     }
 
+    // Size the search pool, older setup files use every core
+    let search_threads = setup
+        .get("search_threads")
+        .and_then(|v| v.as_u64())
+        .map(|threads| threads as usize);
+    set_search_threads(search_threads)?;
+
     // Load the key folders if available
     if let Some(key_folders) = setup.get("key_folders").and_then(|v| v.as_object()) {
         let key_folders_map: HashMap<String, HashMap<String, String>> = key_folders
@@ -298,169 +521,475 @@ async fn load_setup() -> Result<HashMap<String, HashMap<String, String>>, String
 }
 
 //// Functions to Handle Indexing
-/// Scores the filename based on whether it starts with the query string
-fn score_filename(file_name: &str, query: &str) -> i16 {
-    if file_name.starts_with(query) {
-        return 1000; // Return a high score for filenames that start with the query
-    }
-    0
+/// Returns the last component of a path, falling back to the path itself
+fn file_name_from_path(file_path: &str) -> String {
+    Path::new(file_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| file_path.to_string())
 }
 
-/// Saves the index to a file
-async fn save_index(index: &FileIndex, index_path: &Path) -> Result<(), String> {
-    let serialized_data = serde_json::to_string_pretty(index)
-        .map_err(|err| format!("Failed to serialize index: {}", err))?;
+/// Saves an index to a file
+async fn save_index(index: &CompactIndex, index_path: &Path) -> Result<(), String> {
+    index_file::write_index(&index_file::encode_index(index), index_path).await?;
 
-    tokio::fs::write(index_path, serialized_data)
-        .await
-        .map_err(|err| format!("Failed to write index to file: {}", err))?;
+    println!("Index successfully saved.");
+    Ok(())
+}
+
+/// Converts the JSON indexes of older versions to the binary format
+async fn migrate_legacy_indexes(config_dir: &Path) {
+    for (legacy_name, name) in [
+        (LEGACY_FILE_INDEX, FILE_INDEX),
+        (LEGACY_EXTENSIONS_INDEX, EXTENSIONS_INDEX),
+    ] {
+        let legacy_path = config_dir.join(legacy_name);
+        if !legacy_path.exists() || config_dir.join(name).exists() {
+            continue;
+        }
+
+        let migrated = match tokio::fs::read_to_string(&legacy_path).await {
+            Ok(content) => match serde_json::from_str::<FileIndex>(&content) {
+                Ok(index) => {
+                    let index = build_compact_index(&index, |_| true);
+                    save_index(&index, &config_dir.join(name)).await
+                }
+                Err(e) => Err(format!("Failed to parse {}: {}", legacy_name, e)),
+            },
+            Err(e) => Err(format!("Failed to read {}: {}", legacy_name, e)),
+        };
+        match migrated {
+            Ok(()) => {
+                println!("Migrated {} to {}.", legacy_name, name);
+                if let Err(e) = tokio::fs::remove_file(&legacy_path).await {
+                    println!("Failed to remove {}: {}", legacy_name, e);
+                }
+            }
+            // The next indexing run rebuilds the index from scratch
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Saves the in-memory index together with the index of preferred extensions next to it.
+/// Each is encoded under its read lock, which is released before the file is written.
+async fn save_indexes(index_path: &Path) -> Result<(), String> {
+    let encoded = index_file::encode_index(&*IN_MEMORY_INDEX.read().await);
+    index_file::write_index(&encoded, index_path).await?;
+    drop(encoded);
 
+    let encoded = index_file::encode_index(&*EXTENSIONS_MEMORY_INDEX.read().await);
+    index_file::write_index(&encoded, &index_path.with_file_name(EXTENSIONS_INDEX)).await?;
     println!("Index successfully saved.");
     Ok(())
 }
 
-/// Loads the index from a file
-async fn load_index(index_path: &Path) -> Result<(), String> {
-    let file_content = match tokio::fs::read_to_string(index_path).await {
-        Ok(content) => content,
-        Err(_) => {
-            println!("Index file not found or unreadable. Initializing an empty index.");
-            return Ok(()); // Return early with an empty index
+/// Loads the index from a file into the given in-memory index, falling back to its
+/// backup if the file is damaged. Problems are reported to the UI.
+async fn load_index(
+    window: &Window,
+    index_path: &Path,
+    target: &RwLock<CompactIndex>,
+) -> Result<(), String> {
+    // An index of an older format isn't damaged, the next run simply rebuilds it
+    if index_file::is_outdated(index_path) {
+        println!(
+            "{} has an older format and will be rebuilt.",
+            index_path.display()
+        );
+        target.write().await.clear();
+        return Ok(());
+    }
+
+    // Entries go straight from the mapped file into a new compact index on a blocking
+    // thread, so the runtime and searches of the current index aren't held up meanwhile
+    let index_path = index_path.to_path_buf();
+    let loaded = tokio::task::spawn_blocking(move || {
+        storage::load_with_backup(&index_path, |path| {
+            let mut index = CompactIndex::default();
+            let summary = index_file::for_each_entry(path, |details| index.insert(details))?;
+            index.set_rule_fingerprints(summary.rule_fingerprints);
+            Ok((index, summary.entry_count))
+        })
+    })
+    .await
+    .map_err(|e| format!("Failed to load index: {}", e))?;
+
+    match loaded {
+        Ok(((index, entry_count), issue)) => {
+            if let Some(issue) = issue {
+                storage::report_issue(window, &issue);
+            }
+            println!(
+                "Index successfully loaded into memory: {} entries.",
+                entry_count
+            );
+            *target.write().await = index;
+        }
+        Err(issue) => {
+            storage::report_issue(window, &issue);
+            target.write().await.clear();
         }
+    }
+    Ok(())
+}
+
+/// Loads both saved indexes, then picks up the changes made since the last run.
+/// Holds the job lock while loading, so no indexing run starts from an empty index.
+async fn load_saved_indexes(window: Window, config_dir: PathBuf, file_index_path: PathBuf) {
+    let job = jobs::JOB.lock().await;
+    let extensions_index_path = config_dir.join(EXTENSIONS_INDEX);
+    let loaded = match load_index(&window, &file_index_path, &IN_MEMORY_INDEX).await {
+        Ok(()) => load_index(&window, &extensions_index_path, &EXTENSIONS_MEMORY_INDEX).await,
+        Err(e) => Err(e),
     };
+    drop(job);
+    if let Err(e) = loaded {
+        println!("Error loading index: {}", e);
+    }
+    window.emit("index-found", {}).unwrap();
 
-    let index: FileIndex = serde_json::from_str(&file_content).unwrap_or_else(|_| FileIndex {
-        files: HashMap::new(),
-    });
+    if let Err(e) = create_index(window, file_index_path).await {
+        println!("Error indexing files: {}", e);
+    }
+}
+
+/// Copies an in-memory index into a FileIndex, along with its rule fingerprints
+fn file_index_of(index: &CompactIndex) -> FileIndex {
+    FileIndex {
+        files: index
+            .details()
+            .map(|details| (details.file_path.clone(), details))
+            .collect(),
+        rule_fingerprints: index.rule_fingerprints().clone(),
+    }
+}
 
-    let mut in_memory_index = IN_MEMORY_INDEX.lock().await;
-    in_memory_index.clear(); // Clear existing entries
+/// Builds the index entry for a path from its metadata
+fn build_file_details(
+    entry_path: &Path,
+    file_name: String,
+    metadata: &std::fs::Metadata,
+) -> FileDetails {
+    let file_type = if metadata.is_dir() {
+        "directory".to_string()
+    } else if metadata.is_file() {
+        "file".to_string()
+    } else {
+        "unknown".to_string()
+    };
 
-    for (file_name, details) in index.files.iter() {
-        in_memory_index.insert(file_name.clone(), details.file_path.clone());
+    let file_extension = entry_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map_or("".to_string(), |ext| ext.to_string());
+
+    FileDetails {
+        file_name,
+        file_path: raw_path::display(entry_path),
+        file_size: metadata.len(),
+        file_type,
+        creation_date: metadata.created().ok(),
+        modified_date: metadata.modified().ok(),
+        file_extension,
+        ignored: false,
+        scanned: false,
+        raw_path: raw_path::raw_bytes(entry_path),
     }
+}
 
-    println!("Index successfully loaded into memory.");
-    Ok(())
+/// Returns true if the stored details still describe the entry on disk
+fn details_unchanged(old: &FileDetails, new: &FileDetails) -> bool {
+    old.file_type == new.file_type
+        && old.file_size == new.file_size
+        && old.modified_date == new.modified_date
+        && old.ignored == new.ignored
 }
 
-/// Recursively indexes directories and subdirectories
-async fn index_files(
-    path: &Path,
-    file_index: &Arc<tokio::sync::Mutex<FileIndex>>,
-    extensions_index: &Arc<tokio::sync::Mutex<FileIndex>>,
-) {
-    let extensions = get_extensions().await.unwrap_or_else(|_| Vec::new());
-    let queue = Arc::new(tokio::sync::Mutex::new(VecDeque::new()));
-    queue.lock().await.push_back((path.to_path_buf(), 0));
-
-    loop {
-        let (current_path, depth) = {
-            // Scope the lock to avoid holding it throughout the iteration
-            let mut queue_lock = queue.lock().await;
-            match queue_lock.pop_front() {
-                Some(item) => item,
-                None => break, // Exit the loop if the queue is empty
-            }
+/// Groups the indexed paths by their parent directory
+fn children_by_parent(index: &FileIndex) -> HashMap<String, Vec<String>> {
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    for file_path in index.files.keys() {
+        if let Some(parent) = Path::new(file_path).parent() {
+            children
+                .entry(parent.display().to_string())
+                .or_default()
+                .push(file_path.clone());
+        }
+    }
+    children
+}
+
+/// Removes an entry and everything indexed below it, returning the number of removed entries
+fn remove_subtree(
+    index: &mut FileIndex,
+    children: &HashMap<String, Vec<String>>,
+    file_path: &str,
+) -> usize {
+    let mut removed = 0;
+    let mut stack = vec![file_path.to_string()];
+    while let Some(current) = stack.pop() {
+        if index.files.remove(&current).is_some() {
+            removed += 1;
+        }
+        if let Some(child_paths) = children.get(&current) {
+            stack.extend(child_paths.iter().cloned());
+        }
+    }
+    removed
+}
+
+/// Removes entries the exclusion rules now reject, along with everything below them.
+/// Returns the number of removed entries.
+fn remove_excluded(index: &mut FileIndex, root_folder: &Path, rules: &CompiledRules) -> usize {
+    let excluded_directories: HashSet<String> = index
+        .files
+        .iter()
+        .filter(|(file_path, _)| Path::new(file_path).starts_with(root_folder))
+        .filter(|(_, details)| details.file_type == "directory")
+        .filter(|(file_path, _)| rules.excludes(Path::new(file_path), true, 0))
+        .map(|(file_path, _)| file_path.clone())
+        .collect();
+
+    let before = index.files.len();
+    index.files.retain(|file_path, details| {
+        let path = Path::new(file_path);
+        let Ok(relative_path) = path.strip_prefix(root_folder) else {
+            return true; // Belongs to another root
         };
+        let too_deep = relative_path.components().count() > rules.max_depth() + 1;
+        !too_deep
+            && !rules.excludes(path, details.file_type == "directory", details.file_size)
+            && !path.ancestors().skip(1).any(|ancestor| {
+                ancestor
+                    .to_str()
+                    .is_some_and(|ancestor| excluded_directories.contains(ancestor))
+            })
+    });
+    before - index.files.len()
+}
 
-        if depth > DEPTH_STOP {
-            continue; // Skip if depth exceeds the allowed depth
+/// Applies walk batches to the in-memory indexes, locking them once for all batches
+async fn publish_batches(batches: &[WalkBatch], extensions: &HashMap<String, i64>) {
+    let mut in_memory_index = IN_MEMORY_INDEX.write().await;
+    let mut extensions_memory_index = EXTENSIONS_MEMORY_INDEX.write().await;
+    for batch in batches {
+        for details in &batch.updated {
+            in_memory_index.insert(details);
+            if extension_weight(&details.file_extension, extensions).is_some() {
+                extensions_memory_index.insert(details);
+            }
+        }
+        for removed_path in &batch.removed {
+            in_memory_index.remove_subtree(removed_path);
+            extensions_memory_index.remove_subtree(removed_path);
         }
+    }
+}
+
+/// Builds an in-memory index from the entries that pass the filter
+fn build_compact_index(index: &FileIndex, filter: impl Fn(&FileDetails) -> bool) -> CompactIndex {
+    let mut compact_index = CompactIndex::default();
+    for details in index.files.values().filter(|details| filter(details)) {
+        compact_index.insert(details);
+    }
+    compact_index.set_rule_fingerprints(index.rule_fingerprints.clone());
+    compact_index
+}
 
-        if current_path.ends_with(SKIP_DIRECTORY) {
-            continue; // Skip specific directories
+/// Returns where a walk of the root, or of a directory inside it, starts
+fn walk_start(root: &RootConfig, path: &Path) -> QueuedDirectory {
+    let root_folder = Path::new(&root.path);
+
+    // Subtrees indexed by the watcher inherit the ignore files above them
+    let ignored = match path.parent() {
+        Some(parent) if path != root_folder && root.exclusions.respect_ignore_files => {
+            let (ignore_rules, parent_ignored) = IgnoreRules::for_path(root_folder, parent);
+            parent_ignored || ignore_rules.is_ignored(path, true)
         }
+        _ => false,
+    };
+    QueuedDirectory {
+        path: path.to_path_buf(),
+        depth: path
+            .strip_prefix(root_folder)
+            .map_or(0, |relative_path| relative_path.components().count()),
+        known_modified: None,
+        ignored,
+    }
+}
 
-        match tokio::fs::read_dir(&current_path).await {
-            Ok(mut entries) => {
-                while let Ok(Some(entry)) = entries.next_entry().await {
-                    let entry_path = entry.path();
-                    let file_name = match entry.file_name().into_string() {
-                        Ok(name) => name,
-                        Err(_) => {
-                            println!("Encountered a non-UTF-8 file name. Skipping.");
-                            continue;
-                        }
-                    };
-
-                    let file_path = entry_path.display().to_string();
-
-                    match entry.metadata().await {
-                        Ok(metadata) => {
-                            let file_size = metadata.len();
-                            let file_type = if metadata.is_dir() {
-                                "directory".to_string()
-                            } else if metadata.is_file() {
-                                "file".to_string()
-                            } else {
-                                "unknown".to_string()
-                            };
-                            let creation_date = metadata.created().ok();
-
-                            let file_extension = entry_path
-                                .extension()
-                                .and_then(|ext| ext.to_str())
-                                .map_or("".to_string(), |ext| ext.to_string());
-
-                            let details = FileDetails {
-                                file_path: file_path.clone(),
-                                file_size,
-                                file_type: file_type.clone(),
-                                creation_date,
-                                file_extension: file_extension.clone(),
-                            };
-
-                            {
-                                let mut file_index_lock = file_index.lock().await;
-                                file_index_lock
-                                    .files
-                                    .insert(file_name.clone(), details.clone());
-                            }
-
-                            // Uncomment this block if filtering by extensions is needed
-                            // if !extensions.is_empty() && extensions.contains(&file_extension) {
-                            //     let mut extensions_index_lock = extensions_index.lock().await;
-                            //     extensions_index_lock
-                            //         .files
-                            //         .insert(file_name.clone(), details);
-                            // }
-
-                            if entry_path.is_dir() {
-                                let mut queue_lock = queue.lock().await;
-                                queue_lock.push_back((entry_path, depth + 1));
-                            }
-                        }
-                        Err(e) => {
-                            if e.kind() == std::io::ErrorKind::PermissionDenied {
-                                println!(
-                                    "Permission denied for accessing: {}. Skipping.",
-                                    file_path
-                                );
-                            } else {
-                                println!("Error getting metadata for {}: {}", file_path, e);
-                            }
-                        }
-                    }
+/// Indexes directories inside a root, updating the index in place.
+/// The directories are walked in parallel and their changes applied in batches.
+/// Returns the directories left unscanned because the job was asked to stop.
+async fn index_files(
+    root: &RootConfig,
+    starts: Vec<QueuedDirectory>,
+    file_index: &Arc<tokio::sync::Mutex<FileIndex>>,
+    control: &Arc<JobControl>,
+) -> Vec<QueuedDirectory> {
+    let rules = compile_exclusions(root);
+    let root_folder = Path::new(&root.path);
+    let fingerprint = root.exclusions.fingerprint();
+
+    // Drop entries the rules of the root now exclude and remember the directory layout
+    let (previous, children, excluded_entries) = {
+        let mut file_index_lock = file_index.lock().await;
+        let excluded_entries = remove_excluded(&mut file_index_lock, root_folder, &rules);
+        let children = children_by_parent(&file_index_lock);
+        (
+            std::mem::take(&mut *file_index_lock),
+            children,
+            excluded_entries,
+        )
+    };
+
+    // Looser rules let in entries that unchanged directories never listed, so
+    // directories are only skipped if the root was indexed with the same rules
+    let rules_unchanged = previous.rule_fingerprints.get(&root.path) == Some(&fingerprint);
+    if !rules_unchanged {
+        println!(
+            "Exclusion rules of {} changed, reading every directory.",
+            root.path
+        );
+    }
+
+    // The ignore files above each start are read again rather than stored in the queue
+    let starts: Vec<PendingDirectory> = starts
+        .into_iter()
+        .map(|start| {
+            let ignore_rules = match start.path.parent() {
+                Some(parent) if rules.respect_ignore_files() => {
+                    IgnoreRules::for_path(root_folder, parent).0
                 }
+                _ => Arc::new(IgnoreRules::default()),
+            };
+            PendingDirectory {
+                path: start.path,
+                depth: start.depth,
+                known_modified: start.known_modified.filter(|_| rules_unchanged),
+                ignore_rules,
+                ignored: start.ignored,
             }
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::PermissionDenied {
-                    println!(
-                        "Permission denied for directory: {}. Skipping.",
-                        current_path.display()
-                    );
-                } else {
-                    println!("Error reading directory {}: {}", current_path.display(), e);
-                }
+        })
+        .collect();
+
+    // The walk blocks on file system calls, so keep it off the async runtime
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let walk = Walk {
+        rules,
+        previous,
+        children,
+        rules_unchanged,
+        sender,
+        unchanged_directories: AtomicUsize::new(0),
+        control: control.clone(),
+        interrupted: std::sync::Mutex::new(Vec::new()),
+    };
+    let walk_task = tokio::task::spawn_blocking(move || {
+        walk.run(starts);
+        (
+            walk.previous,
+            walk.children,
+            walk.unchanged_directories,
+            walk.interrupted.into_inner().unwrap(),
+        )
+    });
+
+    // Make the batches searchable as they arrive instead of after the walk
+    let extensions = get_extensions().await.unwrap_or_default();
+    let mut batches = Vec::new();
+    while let Some(batch) = receiver.recv().await {
+        let first = batches.len();
+        batches.push(batch);
+        while batches.len() - first < PUBLISH_BATCH_SIZE {
+            match receiver.try_recv() {
+                Ok(batch) => batches.push(batch),
+                Err(_) => break,
             }
         }
+        publish_batches(&batches[first..], &extensions).await;
+    }
+    let (mut index, children, unchanged_directories, interrupted) = match walk_task.await {
+        Ok(result) => result,
+        Err(e) => {
+            println!("Error walking {}: {}", root.path, e);
+            return Vec::new();
+        }
+    };
+
+    let mut updated_entries = 0;
+    let mut removed_entries = excluded_entries;
+    let mut listed = Vec::new();
+    for batch in batches {
+        updated_entries += batch.updated.len();
+        for details in batch.updated {
+            index.files.insert(details.file_path.clone(), details);
+        }
+        for removed_path in batch.removed {
+            removed_entries += remove_subtree(&mut index, &children, &removed_path);
+        }
+        listed.extend(batch.listed);
     }
+
+    // A directory is often listed before its parent reports its new details, so only
+    // flag the listed directories once every batch has been applied. Their own time is
+    // stored too, since a parent that didn't change never reports it again.
+    for (directory_path, modified_date) in listed {
+        if let Some(details) = index.files.get_mut(&directory_path) {
+            details.modified_date = modified_date;
+            details.scanned = true;
+        }
+    }
+
+    // A cancelled run drops its queue, and the next run starts over from the previous
+    // index. The directories left in the queue weren't confirmed by this run, so have the
+    // next one read them instead of trusting their stored modification time.
+    for directory in &interrupted {
+        if let Some(details) = index.files.get_mut(&raw_path::display(&directory.path)) {
+            details.scanned = false;
+        }
+    }
+    if interrupted.is_empty() {
+        index
+            .rule_fingerprints
+            .insert(root.path.clone(), fingerprint);
+    }
+    *file_index.lock().await = index;
+
+    println!(
+        "Indexing finished: {} entries updated, {} removed, {} directories unchanged.",
+        updated_entries,
+        removed_entries,
+        unchanged_directories.into_inner()
+    );
+    if !interrupted.is_empty() {
+        println!(
+            "Indexing stopped with {} directories left to scan.",
+            interrupted.len()
+        );
+    }
+    interrupted
 }
 
 //// Startup function
 #[tauri::command]
 async fn startup(window: Window) -> Result<SetupData, String> {
+    // A crash between writing a file and renaming it over the old one leaves it behind
+    if let Some(config_dir) = config_dir() {
+        storage::remove_temporary_files(
+            &config_dir,
+            &[
+                FILE_INDEX,
+                EXTENSIONS_INDEX,
+                SETUP_FILE,
+                RECENT_FILES,
+                usage::USAGE_LOG,
+                jobs::CHECKPOINT_FILE,
+            ],
+        );
+    }
+
     if !setup_file_check().await.unwrap_or(false) {
         println!("Setup file not found. Prompting user...");
         Ok(SetupData {
@@ -470,20 +999,27 @@ async fn startup(window: Window) -> Result<SetupData, String> {
         })
     } else {
         println!("Setup file found. Loading details...");
-        let key_folders = load_setup().await?;
-        let recent_files = get_recent_data().await?;
+        let key_folders = load_setup(&window).await?;
+        let recent_files = get_recent_data(window.clone()).await?;
+        usage::load_usage_log(&window).await;
 
         let config_dir = config_dir().unwrap();
         let file_index_path = config_dir.join(FILE_INDEX);
-
-        // Check if the main file index exists
-        if file_index_path.exists() {
-            // Load the file index into memory if it exists
-            load_index(&file_index_path).await?;
-            window.emit("index-found", {}).unwrap();
+        migrate_legacy_indexes(&config_dir).await;
+
+        // Check if the main file index or its backup exists
+        if storage::exists(&file_index_path) {
+            // Decoding a large index takes seconds, so the UI comes up meanwhile and
+            // learns through index-found once the saved index is searchable
+            tauri::async_runtime::spawn(load_saved_indexes(
+                window.clone(),
+                config_dir,
+                file_index_path,
+            ));
         } else {
-            create_index(window, file_index_path).await?;
+            create_index(window.clone(), file_index_path).await?;
         }
+        roots::start_scheduler(window);
 
         Ok(SetupData {
             valid: true,
@@ -493,141 +1029,460 @@ async fn startup(window: Window) -> Result<SetupData, String> {
     }
 }
 
+/// Starts an indexing job, superseding the running one. Continues from the checkpoint
+/// of a paused run if there is one.
 async fn create_index(window: Window, file_index_path: PathBuf) -> Result<(), String> {
+    // Held until the new job is registered, so runs can't start concurrently
+    let mut job = jobs::JOB.lock().await;
+    if jobs::stop(&mut job, StopReason::Cancel).await {
+        println!("Superseded the running indexing job.");
+    }
+
     window.emit("indexing-started", {}).unwrap();
 
-    let root_folder = match get_root_folder().await {
-        Ok(folder) => folder,
+    // The run swaps in its own index when it ends, which would drop the changes the
+    // watcher applies meanwhile. The run finds them itself and restarts the watcher.
+    watcher::stop_watcher();
+
+    let roots = match get_roots().await {
+        Ok(roots) => roots,
         Err(e) => {
-            println!("Error getting root folder: {}", e);
+            println!("Error getting index roots: {}", e);
             return Err(e);
         }
     };
+    let checkpoint = jobs::load_checkpoint(&window);
+
+    let run = PROGRESS.start(IN_MEMORY_INDEX.read().await.len());
+    progress::start_reporter(window.clone(), run);
+
+    // Run the indexing in the background
+    let control = Arc::new(JobControl::default());
+    let handle = tauri::async_runtime::spawn(run_index_job(
+        window,
+        roots,
+        file_index_path,
+        checkpoint,
+        control.clone(),
+    ));
+    *job = Some(Job::new(handle, control));
 
-    let start_path = Path::new(&root_folder);
-    let new_file_index = Arc::new(Mutex::new(FileIndex {
-        files: HashMap::new(),
-    }));
-    let new_extensions_index = Arc::new(Mutex::new(FileIndex {
-        files: HashMap::new(),
-    }));
-
-    // Clone the window and file_index_path to move into the async task
-    let window_clone = window.clone();
-    let file_index_path_clone = file_index_path.clone();
-    let start_path_clone = start_path.to_path_buf();
-
-    // Spawn a new task to run the indexing in the background
-    tauri::async_runtime::spawn(async move {
-        println!("Indexing files in the background...");
-        // Index all files in the root folder
-        index_files(&start_path_clone, &new_file_index, &new_extensions_index).await;
-
-        // Save the new indices to the specified paths
-        let file_index = new_file_index.lock().await;
-        if let Err(e) = save_index(&file_index, &file_index_path_clone).await {
-            println!("Error saving index: {}", e);
+    Ok(())
+}
+
+/// Updates every root, saves the index and starts the watcher. A paused run saves the
+/// directories it didn't reach, and the roots it finished, as the checkpoint.
+async fn run_index_job(
+    window: Window,
+    roots: Vec<RootConfig>,
+    file_index_path: PathBuf,
+    checkpoint: Checkpoint,
+    control: Arc<JobControl>,
+) {
+    println!("Indexing files in the background...");
+
+    // Start from the loaded or last built index so unchanged directories can be skipped
+    let previous_index = file_index_of(&*IN_MEMORY_INDEX.read().await);
+    let new_file_index = Arc::new(Mutex::new(previous_index));
+    let mut indexed_roots = Vec::new();
+    let mut completed_roots = Vec::new();
+    let mut pending = Vec::new();
+
+    // Update the index with the changes in every root
+    for root in &roots {
+        if jobs::is_completed(&checkpoint, Path::new(&root.path)) {
+            completed_roots.push(root.path.clone());
+            continue;
+        }
+        let queued: Vec<QueuedDirectory> = checkpoint
+            .pending
+            .iter()
+            .filter(|directory| root.contains(&directory.path))
+            .cloned()
+            .collect();
+        if control.is_stopped() {
+            pending.extend(queued); // Keep the queue of roots this run didn't get to
+            continue;
+        }
+
+        let starts = if queued.is_empty() {
+            println!("Indexing {}...", root.path);
+            vec![walk_start(root, Path::new(&root.path))]
+        } else {
+            println!(
+                "Continuing {} from {} queued directories...",
+                root.path,
+                queued.len()
+            );
+            queued
+        };
+        let interrupted = index_files(root, starts, &new_file_index, &control).await;
+        if interrupted.is_empty() {
+            completed_roots.push(root.path.clone());
+            indexed_roots.push(root.clone());
         } else {
-            window_clone.emit("indexing-completed", {}).unwrap();
+            pending.extend(interrupted);
         }
+    }
+    roots::mark_indexed(&indexed_roots);
+    PROGRESS.finish();
+    progress::emit_progress(&window);
+
+    // Keep the queue only if the run was paused before it got through every root
+    let finished = completed_roots.len() == roots.len();
+    let stop_reason = control.stop_reason();
+    if stop_reason == Some(StopReason::Pause) && !finished {
+        let checkpoint = Checkpoint {
+            completed_roots,
+            pending,
+        };
+        if let Err(e) = jobs::save_checkpoint(&checkpoint).await {
+            println!("Error saving indexing checkpoint: {}", e);
+        }
+    } else {
+        jobs::clear_checkpoint().await;
+    }
+
+    // Drop the entries of roots that were removed
+    let mut file_index = std::mem::take(&mut *new_file_index.lock().await);
+    drop(new_file_index);
+    file_index
+        .files
+        .retain(|file_path, _| root_for_path(&roots, Path::new(file_path)).is_some());
+    file_index
+        .rule_fingerprints
+        .retain(|root_path, _| roots.iter().any(|root| root.path == *root_path));
+
+    // Replace the published batches with the final index, which also drops excluded entries
+    // and removed roots. The preferred extensions may have changed with the setup.
+    let extensions = get_extensions().await.unwrap_or_default();
+    let in_memory_index = build_compact_index(&file_index, |_| true);
+    let extensions_memory_index = build_compact_index(&file_index, |details| {
+        extension_weight(&details.file_extension, &extensions).is_some()
     });
+    *IN_MEMORY_INDEX.write().await = in_memory_index;
+    *EXTENSIONS_MEMORY_INDEX.write().await = extensions_memory_index;
 
-    Ok(())
+    // The compact indexes hold everything from here on, including what is saved
+    drop(file_index);
+
+    // Save the new indices to the specified paths, including what a stopped run found
+    if let Err(e) = save_indexes(&file_index_path).await {
+        println!("Error saving index: {}", e);
+    } else {
+        let event = match stop_reason {
+            _ if finished => "indexing-completed",
+            Some(StopReason::Pause) => "indexing-paused",
+            _ => "indexing-cancelled",
+        };
+        window.emit(event, {}).unwrap();
+
+        // Keep the index up to date from now on
+        if let Err(e) = watcher::start_watcher(window, roots, file_index_path) {
+            println!("Error starting watcher: {}", e);
+        }
+    }
+    control.finish();
+}
+
+//// Index Job Commands
+/// Stops the running indexing job for good, keeping what it found so far.
+/// Also drops the checkpoint of a paused job.
+#[tauri::command]
+async fn cancel_indexing() -> Result<(), String> {
+    let mut job = jobs::JOB.lock().await;
+    if jobs::stop(&mut job, StopReason::Cancel).await {
+        return Ok(());
+    }
+    if jobs::has_checkpoint() {
+        jobs::clear_checkpoint().await;
+        return Ok(());
+    }
+    Err("No indexing job is running".to_string())
+}
+
+/// Stops the running indexing job, saving the directories it didn't reach yet
+#[tauri::command]
+async fn pause_indexing() -> Result<(), String> {
+    let mut job = jobs::JOB.lock().await;
+    if jobs::stop(&mut job, StopReason::Pause).await {
+        Ok(())
+    } else {
+        Err("No indexing job is running".to_string())
+    }
+}
+
+/// Continues a paused indexing job from its checkpoint
+#[tauri::command]
+async fn resume_indexing(window: Window) -> Result<(), String> {
+    if jobs::state().await != JobState::Paused {
+        return Err("No indexing job is paused".to_string());
+    }
+    let file_index_path = config_dir()
+        .ok_or("Failed to retrieve config directory")?
+        .join(FILE_INDEX);
+    create_index(window, file_index_path).await
+}
+
+/// Returns the progress of the running indexing run, or the totals of the last one
+#[tauri::command]
+async fn get_index_status() -> Result<IndexStatus, String> {
+    Ok(PROGRESS.snapshot())
 }
 
 //// Search and Recent Export functions
-/// Searches for files based on the query
+/// Parses the query of a search and applies its options
+fn parse_search(key: &SearchKey) -> Result<SearchQuery, SearchError> {
+    let mut query =
+        parse_query(&key.query).map_err(|diagnostics| SearchError::InvalidQuery { diagnostics })?;
+    query.include_ignored = key.include_ignored;
+    query.set_folding(key.folding);
+    Ok(query)
+}
+
+/// Collects everything besides the query that ranks the matches
+async fn current_ranking() -> Result<Ranking, String> {
+    Ok(Ranking {
+        extensions: get_extensions().await?,
+        frecency: usage::frecency_scores().await,
+        now: SystemTime::now(),
+    })
+}
+
+/// Returns the in-memory index a search reads, under the shared read lock
+async fn search_index(preferred_only: bool) -> RwLockReadGuard<'static, CompactIndex> {
+    if preferred_only {
+        EXTENSIONS_MEMORY_INDEX.read().await
+    } else {
+        IN_MEMORY_INDEX.read().await
+    }
+}
+
+/// Turns up to limit matches from offset on into results, without their roots
+fn page_of(
+    index: &CompactIndex,
+    matches: &[(i64, u32)],
+    query: &SearchQuery,
+    offset: usize,
+    limit: usize,
+) -> Vec<SearchResult> {
+    let mut path_buffer = String::new();
+    matches
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(|(score, slot)| {
+            let details = index.view(&index.entries()[*slot as usize], &mut path_buffer);
+            let (name_ranges, path_ranges) =
+                match_ranges(details.file_name, details.file_path, query);
+            SearchResult {
+                file_name: details.file_name.to_string(),
+                file_path: details.file_path.to_string(),
+                root: String::new(),
+                score: *score,
+                name_ranges,
+                path_ranges,
+            }
+        })
+        .collect()
+}
+
+/// Tags every result with the root it was found in
+fn tag_roots(roots: &[RootConfig], mut page: Vec<SearchResult>) -> Vec<SearchResult> {
+    for result in &mut page {
+        result.root = root_for_path(roots, Path::new(&result.file_path))
+            .map(|root| root.path.clone())
+            .unwrap_or_default();
+    }
+    page
+}
+
+/// Searches for files based on the query, optionally only among the preferred extensions.
+/// Entries matched by ignore files are left out unless include_ignored is set. Case and
+/// accents are ignored unless case_sensitive or accent_sensitive is set.
+/// Returns the page of limit results starting at offset in the given order, along with the
+/// total number of matches. Later pages of the same search reuse the sorted matches as long
+/// as the index hasn't changed. Works while indexing runs, flagging the results as partial.
+/// Fails with SearchError::Cancelled once a newer search starts, so rapid typing doesn't
+/// leave a queue of full scans behind.
 #[tauri::command]
-async fn search_files(query: String) -> Result<Vec<(String, String)>, String> {
+#[allow(clippy::too_many_arguments)] // Every option is its own argument of the command
+async fn search_files(
+    query: String,
+    preferred_only: Option<bool>,
+    include_ignored: Option<bool>,
+    case_sensitive: Option<bool>,
+    accent_sensitive: Option<bool>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    sort: Option<SortBy>,
+) -> Result<SearchResults, SearchError> {
     let start_time = Instant::now(); // Start the timer
+    let token = SearchToken::start();
+    let partial = PROGRESS.is_running() || jobs::has_checkpoint();
+    let key = SearchKey::new(
+        query,
+        preferred_only,
+        include_ignored,
+        case_sensitive,
+        accent_sensitive,
+        sort,
+    );
+    let limit = limit.unwrap_or(DEFAULT_RESULT_LIMIT);
+    let offset = offset.unwrap_or(0);
+    let query = parse_search(&key)?;
 
-    // Acquire a lock on the in-memory index
-    let index_guard = IN_MEMORY_INDEX.lock().await;
-    let index_snapshot = index_guard.clone(); // Clone the index for use outside the lock
-    drop(index_guard); // Release the lock early
+    // Search under the shared read lock instead of copying the index
+    let index_guard = search_index(key.preferred_only).await;
 
-    // Convert the query to lowercase for case-insensitive comparison
-    let query_lower = query.to_lowercase();
-
-    // Determine if parallel processing is needed
-    let results: Vec<(String, String)> = if index_snapshot.len() > 1000 {
-        println!("Parallelizing search with Rayon...");
-        println!("Index size: {}", index_snapshot.len());
-        let num_cores = num_cpus::get();
-        println!("Using {} cores for parallel processing.", num_cores);
-
-        let pool = rayon::ThreadPoolBuilder::new()
-            .num_threads(num_cores)
-            .build()
-            .map_err(|e| format!("Failed to build Rayon thread pool: {}", e))?;
-
-        pool.install(|| {
-            index_snapshot
-                .par_iter()
-                .filter_map(|(file_name, file_path)| {
-                    let cleaned_file_name = Path::new(file_name)
-                        .file_stem()
-                        .and_then(|stem| stem.to_str())
-                        .unwrap_or("");
-
-                    // Convert the filename to lowercase for case-insensitive comparison
-                    let cleaned_file_name_lower = cleaned_file_name.to_lowercase();
-
-                    // Calculate the similarity score
-                    let score = score_filename(&cleaned_file_name_lower, &query_lower);
-                    if score >= MINIMUM_SCORE {
-                        Some((file_name.clone(), file_path.clone())) // Return matching file names and paths
-                    } else {
-                        None // Filter out non-matching results
-                    }
-                })
-                .collect()
-        })
-    } else {
-        // For small datasets, use sequential iteration
-        println!("Using sequential search...");
-        index_snapshot
-            .iter()
-            .filter_map(|(file_name, file_path)| {
-                let cleaned_file_name = Path::new(file_name)
-                    .file_stem()
-                    .and_then(|stem| stem.to_str())
-                    .unwrap_or("");
-
-                // Convert the filename to lowercase for case-insensitive comparison
-                let cleaned_file_name_lower = cleaned_file_name.to_lowercase();
-
-                // Calculate the similarity score
-                let score = score_filename(&cleaned_file_name_lower, &query_lower);
-                if score >= MINIMUM_SCORE {
-                    Some((file_name.clone(), file_path.clone())) // Return matching file names and paths
-                } else {
-                    None // Filter out non-matching results
-                }
-            })
-            .collect()
+    // The first page always searches again, so it reflects the latest ranking
+    let cached = LAST_SEARCH.lock().await.take().filter(|last| {
+        offset > 0 && last.key == key && last.generation == index_guard.generation()
+    });
+    let matches = match cached {
+        Some(last) => last.matches,
+        None => {
+            let ranking = current_ranking().await?;
+            let Some(mut matches) =
+                find_matches(&index_guard, &query, &ranking, token, &mut |_| {})
+            else {
+                println!("Search was cancelled by a newer one.");
+                return Err(SearchError::Cancelled);
+            };
+            sort_matches(&index_guard, &mut matches, key.sort);
+            matches
+        }
     };
 
+    // Only the entries on the page are turned into paths
+    let total = matches.len();
+    let page = page_of(&index_guard, &matches, &query, offset, limit);
+    *LAST_SEARCH.lock().await = Some(CachedSearch {
+        key,
+        generation: index_guard.generation(),
+        matches,
+    });
+    drop(index_guard); // Release the lock early
+
+    let results = tag_roots(&get_roots().await?, page);
+
     let duration = start_time.elapsed();
     println!("Search completed in {:?}", duration);
 
-    Ok(results)
+    Ok(SearchResults {
+        results,
+        total,
+        partial,
+    })
+}
+
+/// Searches like search_files, but sends the best limit matches found so far as
+/// "search-results" events tagged with query_id while the scan runs. The likeliest
+/// matches are scored first, and the first batch goes out once FIRST_BATCH_SIZE of them
+/// are found. The scan is cancelled like in search_files once any newer search starts,
+/// and no batch is sent after that. Later pages of the finished search come from
+/// search_files.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)] // Every option is its own argument of the command
+async fn stream_search(
+    window: Window,
+    query_id: u64,
+    query: String,
+    preferred_only: Option<bool>,
+    include_ignored: Option<bool>,
+    case_sensitive: Option<bool>,
+    accent_sensitive: Option<bool>,
+    limit: Option<usize>,
+    sort: Option<SortBy>,
+) -> Result<(), SearchError> {
+    let start_time = Instant::now();
+    let token = SearchToken::start();
+
+    let partial = PROGRESS.is_running() || jobs::has_checkpoint();
+    let key = SearchKey::new(
+        query,
+        preferred_only,
+        include_ignored,
+        case_sensitive,
+        accent_sensitive,
+        sort,
+    );
+    let limit = limit.unwrap_or(DEFAULT_RESULT_LIMIT);
+    let query = parse_search(&key)?;
+    let ranking = current_ranking().await?;
+    let roots = get_roots().await?;
+    let index_guard = search_index(key.preferred_only).await;
+
+    // Sends the first limit of the sorted matches
+    let emit_batch = |sorted: &[(i64, u32)], total: usize, done: bool| {
+        let batch = SearchBatch {
+            query_id,
+            results: tag_roots(&roots, page_of(&index_guard, sorted, &query, 0, limit)),
+            total,
+            partial,
+            done,
+        };
+        window
+            .emit("search-results", batch)
+            .unwrap_or_else(|e| println!("Error emitting search results: {}", e));
+    };
+
+    let mut last_batch: Option<Instant> = None;
+    let mut on_chunk = |matches: &[(i64, u32)]| {
+        let due = match last_batch {
+            None => matches.len() >= FIRST_BATCH_SIZE,
+            Some(sent) => sent.elapsed() >= STREAM_INTERVAL,
+        };
+        if due {
+            let top = top_matches(&index_guard, matches, key.sort, limit);
+            emit_batch(&top, matches.len(), false);
+            last_batch = Some(Instant::now());
+        }
+    };
+
+    let Some(mut matches) = find_matches(&index_guard, &query, &ranking, token, &mut on_chunk)
+    else {
+        println!("Search {} was cancelled by a newer one.", query_id);
+        return Err(SearchError::Cancelled);
+    };
+
+    sort_matches(&index_guard, &mut matches, key.sort);
+    if token.is_cancelled() {
+        return Err(SearchError::Cancelled);
+    }
+    emit_batch(&matches, matches.len(), true);
+    *LAST_SEARCH.lock().await = Some(CachedSearch {
+        key,
+        generation: index_guard.generation(),
+        matches,
+    });
+
+    println!(
+        "Search {} completed in {:?}",
+        query_id,
+        start_time.elapsed()
+    );
+    Ok(())
+}
+
+/// Returns the full details of the given search results
+#[tauri::command]
+async fn get_file_details(paths: Vec<String>) -> Result<Vec<FileDetails>, String> {
+    let in_memory_index = IN_MEMORY_INDEX.read().await;
+    Ok(paths
+        .iter()
+        .filter_map(|file_path| in_memory_index.get(file_path))
+        .collect())
 }
 
 /// Function to save the most recently opened files into recent_files.json
 #[tauri::command]
 async fn process_recent(data: Vec<(i32, (String, String))>) -> Result<(), String> {
     let data_map: HashMap<i32, (String, String)> = data.into_iter().collect();
-    let file_path: PathBuf = config_dir().unwrap().join("recent_files.json");
+    let file_path: PathBuf = config_dir().unwrap().join(RECENT_FILES);
 
-    // Serialize file names and file paths into JSON
-    let json_data = serde_json::to_string_pretty(&data_map)
-        .map_err(|err| format!("Failed to serialize data: {}", err))?;
-
-    // Write JSON data to recent_files.json
-    fs::write(&file_path, json_data.as_bytes())
-        .await
-        .map_err(|err| format!("Failed to write data to recent_files.json: {}", err))?;
+    // Write file names and file paths to recent_files.json as JSON
+    storage::write_json(&file_path, &data_map).await?;
 
     println!("Recent files successfully saved to recent_files.json");
     Ok(())
@@ -635,28 +1490,30 @@ async fn process_recent(data: Vec<(i32, (String, String))>) -> Result<(), String
 
 /// Function to retrieve the most recently opened files from recent_files.json
 #[tauri::command]
-async fn get_recent_data() -> Result<Vec<(i32, (String, String))>, String> {
-    let file_path = config_dir().unwrap().join("recent_files.json");
+async fn get_recent_data(window: Window) -> Result<Vec<(i32, (String, String))>, String> {
+    let file_path = config_dir().unwrap().join(RECENT_FILES);
 
     // If the file doesn't exist, return an empty vector
-    if !file_path.exists() {
+    if !storage::exists(&file_path) {
         println!("recent_files.json not found, returning an empty vector.");
         return Ok(Vec::new());
     }
 
-    // Read the file asynchronously
-    let mut file = fs::File::open(&file_path)
-        .await
-        .map_err(|err| format!("Failed to open recent_files.json: {}", err))?;
-
-    let mut json_content = String::new();
-    file.read_to_string(&mut json_content)
-        .await
-        .map_err(|err| format!("Failed to read recent_files.json: {}", err))?;
-
-    // Deserialize JSON data into HashMap<i32, (String, String)>
-    let data: HashMap<i32, (String, String)> = serde_json::from_str(&json_content)
-        .map_err(|err| format!("Failed to parse recent_files.json: {}", err))?;
+    // Deserialize JSON data into HashMap<i32, (String, String)>, falling back to the backup
+    let data: HashMap<i32, (String, String)> =
+        match storage::load_with_backup(&file_path, storage::read_json) {
+            Ok((data, issue)) => {
+                if let Some(issue) = issue {
+                    storage::report_issue(&window, &issue);
+                }
+                data
+            }
+            Err(issue) => {
+                // Losing the recent files isn't worth failing startup over
+                storage::report_issue(&window, &issue);
+                return Ok(Vec::new());
+            }
+        };
 
     // Convert HashMap to Vec<(i32, (String, String))>
     let vec_data: Vec<(i32, (String, String))> = data.into_iter().collect();
@@ -665,13 +1522,147 @@ async fn get_recent_data() -> Result<Vec<(i32, (String, String))>, String> {
     Ok(vec_data)
 }
 
-#[tauri::command]
-fn open_file(app_handle: tauri::AppHandle, path: String) -> Result<(), String> {
-    let shell_scope = app_handle.shell_scope();
-    tauri::api::shell::open(&shell_scope, path, None)
+/// Opens a path the shell API can't take, since it only accepts strings, with the file
+/// opener of the platform. Its exit code isn't checked, explorer reports 1 on success.
+async fn open_raw_path(path: &Path) -> Result<(), String> {
+    let opener = match OS {
+        "windows" => "explorer",
+        "macos" => "open",
+        _ => "xdg-open",
+    };
+    tokio::process::Command::new(opener)
+        .arg(path)
+        .status()
+        .await
+        .map(drop)
         .map_err(|e| format!("Failed to open file: {}", e))
 }
 
+/// Opens a file with the default application and records the visit for ranking
+#[tauri::command]
+async fn open_file(app_handle: tauri::AppHandle, path: String) -> Result<(), String> {
+    // Search results carry the display path, names that aren't valid Unicode are
+    // opened by their exact path
+    let raw_path = IN_MEMORY_INDEX
+        .read()
+        .await
+        .get(&path)
+        .and_then(|details| details.raw_path);
+    match raw_path {
+        Some(raw_path) => open_raw_path(&raw_path::from_bytes(&raw_path)).await?,
+        None => {
+            let shell_scope = app_handle.shell_scope();
+            tauri::api::shell::open(&shell_scope, path.clone(), None)
+                .map_err(|e| format!("Failed to open file: {}", e))?;
+        }
+    }
+
+    // Failing to record the visit shouldn't fail the open
+    if let Err(e) = usage::record_open(&path).await {
+        println!("Error recording file usage: {}", e);
+    }
+    Ok(())
+}
+
+//// Index Root Commands
+/// Lists the index roots with their settings
+#[tauri::command]
+async fn list_roots() -> Result<Vec<RootConfig>, String> {
+    get_roots().await
+}
+
+/// Adds a root, or replaces the settings of an existing root with the same path,
+/// and reindexes
+#[tauri::command]
+async fn add_root(
+    window: Window,
+    path: String,
+    exclusions: Option<ExclusionRules>,
+    reindex_interval_minutes: Option<u64>,
+) -> Result<Vec<RootConfig>, String> {
+    let mut roots = get_roots().await?;
+    let root = RootConfig {
+        path,
+        exclusions: exclusions.unwrap_or_default(),
+        reindex_interval_minutes,
+    };
+    validate_root(&roots, &root)?;
+    upsert_root(&mut roots, root);
+
+    save_roots(&roots).await?;
+    set_roots(roots.clone()).await?;
+
+    // The root may have been finished by a paused run with its old settings
+    jobs::clear_checkpoint().await;
+
+    let file_index_path = config_dir()
+        .ok_or("Failed to retrieve config directory")?
+        .join(FILE_INDEX);
+    create_index(window, file_index_path).await?;
+    Ok(roots)
+}
+
+/// Removes a root and drops its entries from the index
+#[tauri::command]
+async fn remove_root(window: Window, path: String) -> Result<Vec<RootConfig>, String> {
+    let mut roots = get_roots().await?;
+    let before = roots.len();
+    roots.retain(|root| Path::new(&root.path) != Path::new(&path));
+    if roots.len() == before {
+        return Err(format!("{} is not an index root", path));
+    }
+
+    save_roots(&roots).await?;
+    set_roots(roots.clone()).await?;
+
+    // Hide the entries right away, the reindex drops them from the saved index
+    IN_MEMORY_INDEX.write().await.remove_subtree(&path);
+    EXTENSIONS_MEMORY_INDEX.write().await.remove_subtree(&path);
+
+    let file_index_path = config_dir()
+        .ok_or("Failed to retrieve config directory")?
+        .join(FILE_INDEX);
+    create_index(window, file_index_path).await?;
+    Ok(roots)
+}
+
+/// Lists the indexed paths of a root that the given rules would drop, without applying them
+#[tauri::command]
+async fn preview_exclusions(
+    root: String,
+    rules: ExclusionRules,
+    limit: Option<usize>,
+) -> Result<ExclusionPreview, String> {
+    let rules = rules.compile()?;
+    let root_folder = PathBuf::from(root);
+
+    let mut paths = Vec::new();
+    {
+        let in_memory_index = IN_MEMORY_INDEX.read().await;
+        let mut path_buffer = String::new();
+        for entry in in_memory_index.entries() {
+            if entry.is_removed() {
+                continue;
+            }
+            let details = in_memory_index.view(entry, &mut path_buffer);
+            let path = Path::new(details.file_path);
+            if !path.starts_with(&root_folder) {
+                continue;
+            }
+            if rules.excludes(path, details.file_type == "directory", details.file_size)
+                || rules.excludes_ancestors(&root_folder, path)
+            {
+                paths.push(details.file_path.to_string());
+            }
+        }
+    }
+
+    let total = paths.len();
+    paths.sort_unstable();
+    paths.truncate(limit.unwrap_or(PREVIEW_LIMIT));
+    Ok(ExclusionPreview { total, paths })
+}
+
 #[tokio::main]
 async fn main() {
     tauri::Builder::default()
@@ -679,10 +1670,72 @@ async fn main() {
             startup,
             save_setup_file,
             search_files,
+            stream_search,
+            get_file_details,
             process_recent,
             get_recent_data,
-            open_file
+            open_file,
+            preview_exclusions,
+            list_roots,
+            add_root,
+            remove_root,
+            get_index_status,
+            cancel_indexing,
+            pause_indexing,
+            resume_indexing
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app_handle, event| {
+            // Save the queue of a running index job so the next start continues from it.
+            // main runs inside the tokio runtime, which can't block on itself.
+            if let tauri::RunEvent::ExitRequested { .. } = event {
+                let pause = std::thread::spawn(|| {
+                    tauri::async_runtime::block_on(jobs::pause_for_exit());
+                });
+                if pause.join().is_err() {
+                    println!("Error pausing indexing before exiting.");
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Indexes the whole root into the index, like a run of create_index would
+    async fn index_root(root: &RootConfig, file_index: &Arc<Mutex<FileIndex>>) {
+        let start = walk_start(root, Path::new(&root.path));
+        index_files(root, vec![start], file_index, &Arc::default()).await;
+    }
+
+    #[tokio::test]
+    async fn reread_directories_store_their_own_modification_time() {
+        let root_folder = std::env::temp_dir().join(format!("reindex-{}", std::process::id()));
+        let changed = root_folder.join("parent").join("changed");
+        std::fs::create_dir_all(&changed).unwrap();
+        std::fs::write(changed.join("first.txt"), "first").unwrap();
+        let root = RootConfig::new(root_folder.display().to_string(), ExclusionRules::default());
+        let file_index = Arc::new(Mutex::new(FileIndex::default()));
+        index_root(&root, &file_index).await;
+
+        // Only the directory itself changes, its parent keeps its modification time
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(changed.join("second.txt"), "second").unwrap();
+        index_root(&root, &file_index).await;
+
+        let index = file_index.lock().await;
+        let stored = index.files[&changed.display().to_string()].clone();
+        let current = std::fs::metadata(&changed).unwrap().modified().unwrap();
+        let second_found = index
+            .files
+            .contains_key(&changed.join("second.txt").display().to_string());
+        drop(index);
+        std::fs::remove_dir_all(&root_folder).unwrap();
+
+        assert!(second_found);
+        assert!(stored.scanned);
+        assert_eq!(stored.modified_date, Some(current));
+    }
 }
@@ -0,0 +1,143 @@
+//// Unicode folding
+// Names are compared in a folded form, so `resume` finds `Résumé.pdf` whether the name was
+// stored composed (NFC) or decomposed (NFD, as on files copied from macOS). Each character
+// is folded together with the combining marks after it: compatibility decomposition (NFKD),
+// then case folding and stripping the marks unless the search is case or accent sensitive.
+// Folding segment by segment keeps track of which original characters every folded
+// character came from, which is what match highlighting needs.
+//
+// ASCII characters are kept as they are in fold, because the fuzzy matcher ignores ASCII
+// case itself and uses it to spot word boundaries. key also lowers them, for exact
+// comparisons such as phrases and the trigram indexes.
+
+//// Imports
+use caseless::default_case_fold_str;
+use std::borrow::Cow;
+use std::ops::Range;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+//// Data Structures
+/// How text is folded before it is compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Folding {
+    pub case_sensitive: bool,
+    pub accent_sensitive: bool,
+}
+
+/// Folded text along with the original characters behind each folded character
+#[derive(Debug, Clone, Default)]
+pub struct Folded {
+    pub text: String,
+    sources: Vec<Range<usize>>, // Original character range of every folded character
+}
+
+//// Folding
+impl Folding {
+    /// The folding of the trigram indexes and of the names stored in the compact index,
+    /// which ignores both case and accents. It is also the default.
+    pub const LOOSE: Folding = Folding {
+        case_sensitive: false,
+        accent_sensitive: false,
+    };
+
+    /// Folds text for the fuzzy matcher, leaving ASCII characters as they are
+    pub fn fold(self, text: &str) -> Cow<'_, str> {
+        if text.is_ascii() {
+            return Cow::Borrowed(text);
+        }
+        let mut folded = String::with_capacity(text.len());
+        self.fold_into(text, &mut folded);
+        Cow::Owned(folded)
+    }
+
+    /// Same as fold, appending the folded text to a buffer instead of allocating
+    pub fn fold_into(self, text: &str, folded: &mut String) {
+        if text.is_ascii() {
+            folded.push_str(text);
+            return;
+        }
+        for (segment, _) in segments(text) {
+            self.fold_segment(segment, folded);
+        }
+    }
+
+    /// Folds text for exact comparisons, lowering ASCII as well unless case matters
+    pub fn key(self, text: &str) -> String {
+        let mut key = self.fold(text).into_owned();
+        if !self.case_sensitive {
+            key.make_ascii_lowercase();
+        }
+        key
+    }
+
+    /// Same as fold, keeping the original characters behind each folded character
+    pub fn fold_mapped(self, text: &str) -> Folded {
+        let mut folded = Folded::default();
+        for (segment, source) in segments(text) {
+            let before = folded.text.len();
+            self.fold_segment(segment, &mut folded.text);
+            let added = folded.text[before..].chars().count();
+            folded.sources.extend(std::iter::repeat_n(source, added));
+        }
+        folded
+    }
+
+    /// Same as key, keeping the original characters behind each folded character
+    pub fn key_mapped(self, text: &str) -> Folded {
+        let mut folded = self.fold_mapped(text);
+        if !self.case_sensitive {
+            folded.text.make_ascii_lowercase();
+        }
+        folded
+    }
+
+    fn fold_segment(self, segment: &str, folded: &mut String) {
+        if segment.is_ascii() {
+            folded.push_str(segment);
+            return;
+        }
+        let decomposed: String = segment.nfkd().collect();
+        let cased = if self.case_sensitive {
+            decomposed
+        } else {
+            default_case_fold_str(&decomposed)
+        };
+        if self.accent_sensitive {
+            folded.extend(cased.nfkc());
+        } else {
+            folded.extend(cased.nfkd().filter(|c| !is_combining_mark(*c)));
+        }
+    }
+}
+
+impl Folded {
+    /// Maps character indices of the folded text to the original character indices
+    pub fn source_indices(&self, indices: &[usize]) -> Vec<usize> {
+        indices
+            .iter()
+            .filter_map(|index| self.sources.get(*index))
+            .flat_map(Range::clone)
+            .collect()
+    }
+}
+
+/// Splits text into characters followed by their combining marks, along with the range
+/// of character indices each segment covers
+fn segments(text: &str) -> Vec<(&str, Range<usize>)> {
+    let mut segments: Vec<(&str, Range<usize>)> = Vec::new();
+    let mut start = 0; // Byte offset of the current segment
+    let mut source = 0..0;
+    for (offset, (position, c)) in text.char_indices().enumerate() {
+        if offset > 0 && !is_combining_mark(c) {
+            segments.push((&text[start..position], source.clone()));
+            start = position;
+            source = offset..offset;
+        }
+        source.end = offset + 1;
+    }
+    if !text.is_empty() {
+        segments.push((&text[start..], source));
+    }
+    segments
+}
@@ -0,0 +1,198 @@
+//// Indexing progress
+// The walker threads bump shared counters while create_index runs. A reporter task turns
+// them into throttled "indexing-progress" events, and get_index_status returns the same
+// snapshot on demand. The counters only move during a full indexing run, so the watcher
+// rescanning a single directory doesn't disturb the numbers of the last run.
+
+//// Imports
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::Window;
+
+//// Constants
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250); // At most four events a second
+
+//// Global Variables
+pub static PROGRESS: Lazy<IndexProgress> = Lazy::new(IndexProgress::default);
+
+//// Data Structures
+/// Counters of the current or last indexing run
+#[derive(Default)]
+pub struct IndexProgress {
+    running: AtomicBool,
+    run: AtomicU64, // Id of the latest run, reporters of older runs stop
+    directories_scanned: AtomicU64,
+    files_indexed: AtomicU64,
+    bytes_seen: AtomicU64,
+    errors_skipped: AtomicU64,
+    expected_entries: AtomicU64, // Size of the previous index, zero if there was none
+    current_path: Mutex<Option<String>>,
+    started: Mutex<Option<Instant>>,
+    finished: Mutex<Option<Duration>>, // How long the last run took
+}
+
+/// Snapshot of the indexing progress, sent to the UI
+#[derive(Serialize, Debug, Clone)]
+pub struct IndexStatus {
+    pub running: bool,
+    pub directories_scanned: u64,
+    pub files_indexed: u64,
+    pub bytes_seen: u64,
+    pub current_path: Option<String>,
+    pub errors_skipped: u64,
+    pub elapsed_ms: u64,
+    pub entries_per_second: f64,
+    pub estimated_remaining_ms: Option<u64>, // None until there is a previous index to compare to
+}
+
+//// Counting
+impl IndexProgress {
+    /// Resets the counters for a new run expected to visit about expected_entries entries.
+    /// Returns the id of the run.
+    pub fn start(&self, expected_entries: usize) -> u64 {
+        for counter in [
+            &self.directories_scanned,
+            &self.files_indexed,
+            &self.bytes_seen,
+            &self.errors_skipped,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.expected_entries
+            .store(expected_entries as u64, Ordering::Relaxed);
+        *self.current_path.lock().unwrap() = None;
+        *self.started.lock().unwrap() = Some(Instant::now());
+        *self.finished.lock().unwrap() = None;
+        self.running.store(true, Ordering::SeqCst);
+        self.run.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub fn finish(&self) {
+        if !self.running.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let elapsed = self
+            .started
+            .lock()
+            .unwrap()
+            .map(|started| started.elapsed());
+        *self.finished.lock().unwrap() = elapsed;
+        *self.current_path.lock().unwrap() = None;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Returns true while the given run is the latest one and hasn't finished
+    fn is_current(&self, run: u64) -> bool {
+        self.is_running() && self.run.load(Ordering::SeqCst) == run
+    }
+
+    pub fn directory_scanned(&self, path: &Path) {
+        if !self.is_running() {
+            return;
+        }
+        self.directories_scanned.fetch_add(1, Ordering::Relaxed);
+        *self.current_path.lock().unwrap() = Some(path.display().to_string());
+    }
+
+    /// Counts an entry seen by the walk, whether or not it changed since the last run
+    pub fn entry_seen(&self, is_dir: bool, size: u64) {
+        if !self.is_running() || is_dir {
+            return; // Directories are counted once they are scanned
+        }
+        self.files_indexed.fetch_add(1, Ordering::Relaxed);
+        self.bytes_seen.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// Counts an entry or directory skipped because it couldn't be read
+    pub fn error_skipped(&self) {
+        if self.is_running() {
+            self.errors_skipped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> IndexStatus {
+        let running = self.is_running();
+        let elapsed = if running {
+            self.started
+                .lock()
+                .unwrap()
+                .map(|started| started.elapsed())
+        } else {
+            *self.finished.lock().unwrap()
+        }
+        .unwrap_or_default();
+
+        let directories_scanned = self.directories_scanned.load(Ordering::Relaxed);
+        let files_indexed = self.files_indexed.load(Ordering::Relaxed);
+        let visited = directories_scanned + files_indexed;
+        let entries_per_second = if elapsed.as_secs_f64() > 0.0 {
+            visited as f64 / elapsed.as_secs_f64()
+        } else {
+            0.0
+        };
+
+        // Assume this run visits about as many entries as the previous index holds
+        let expected = self.expected_entries.load(Ordering::Relaxed);
+        let estimated_remaining_ms = (running && visited > 0 && expected > visited).then(|| {
+            let remaining = (expected - visited) as f64 / visited as f64;
+            (elapsed.as_secs_f64() * remaining * 1000.0) as u64
+        });
+
+        IndexStatus {
+            running,
+            directories_scanned,
+            files_indexed,
+            bytes_seen: self.bytes_seen.load(Ordering::Relaxed),
+            current_path: self.current_path.lock().unwrap().clone(),
+            errors_skipped: self.errors_skipped.load(Ordering::Relaxed),
+            elapsed_ms: elapsed.as_millis() as u64,
+            entries_per_second,
+            estimated_remaining_ms,
+        }
+    }
+}
+
+//// Reporting
+/// Emits the progress to the UI every PROGRESS_INTERVAL until the run finishes or a newer
+/// run starts its own reporter. run_index_job emits the final numbers.
+pub fn start_reporter(window: Window, run: u64) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(PROGRESS_INTERVAL).await;
+            if !PROGRESS.is_current(run) {
+                break;
+            }
+            emit_progress(&window);
+        }
+    });
+}
+
+pub fn emit_progress(window: &Window) {
+    window
+        .emit("indexing-progress", PROGRESS.snapshot())
+        .unwrap_or_else(|e| println!("Error emitting indexing progress: {}", e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_latest_run_is_current() {
+        let progress = IndexProgress::default();
+        let superseded = progress.start(0);
+        let latest = progress.start(0);
+        assert!(!progress.is_current(superseded));
+        assert!(progress.is_current(latest));
+
+        progress.finish();
+        assert!(!progress.is_current(latest));
+    }
+}
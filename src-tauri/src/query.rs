@@ -0,0 +1,443 @@
+//// Search query parsing
+// Turns input such as `ext:rs size:>10MB modified:<7d path:src/ type:dir -test "exact phrase"`
+// into a SearchQuery that search_files can match against indexed entries. Sizes without an
+// operator mean at least that size and ages mean newer than that age, so `size:10MB` and
+// `modified:7d` read the way they are usually meant.
+
+//// Imports
+use crate::compact_index::FileView;
+use crate::normalize::Folding;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime};
+
+//// Constants
+const SIZE_UNITS: [(&str, u64); 9] = [
+    ("b", 1),
+    ("k", 1 << 10),
+    ("kb", 1 << 10),
+    ("m", 1 << 20),
+    ("mb", 1 << 20),
+    ("g", 1 << 30),
+    ("gb", 1 << 30),
+    ("t", 1 << 40),
+    ("tb", 1 << 40),
+];
+const AGE_UNITS: [(&str, u64); 9] = [
+    ("s", 1),
+    ("m", 60),
+    ("min", 60),
+    ("h", 60 * 60),
+    ("d", 24 * 60 * 60),
+    ("w", 7 * 24 * 60 * 60),
+    ("mo", 30 * 24 * 60 * 60),
+    ("y", 365 * 24 * 60 * 60),
+    ("yr", 365 * 24 * 60 * 60),
+];
+
+//// Data Structures
+/// Problem found while parsing a query, with the character range it applies to
+#[derive(Serialize, Debug, Clone)]
+pub struct QueryDiagnostic {
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
+/// How a filter value compares to the value of an entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+/// A single `key:value` filter
+#[derive(Debug, Clone)]
+pub enum FilterKind {
+    Extension(Vec<String>),
+    Size(Comparison, u64),
+    Modified(Comparison, Duration), // Compares the age of the entry
+    Created(Comparison, Duration),  // Compares the age of the entry
+    Path(String),
+    Type(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub negated: bool,
+    pub kind: FilterKind,
+}
+
+/// Parsed search query
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub terms: Vec<String>,    // Fuzzy matched against the file name and path
+    pub phrases: Vec<String>,  // Must appear in the file name as is
+    pub excluded: Vec<String>, // Must not appear in the file name
+    pub filters: Vec<Filter>,
+    pub include_ignored: bool, // Set by search_files, not part of the query syntax
+    pub folding: Folding,      // Set by search_files through set_folding
+}
+
+/// Whitespace separated piece of the query
+struct Token {
+    text: String,
+    start: usize,
+    end: usize,
+    negated: bool,
+    quoted: bool,
+}
+
+//// Parsing
+/// Parses the raw query, collecting every problem instead of stopping at the first
+pub fn parse_query(input: &str) -> Result<SearchQuery, Vec<QueryDiagnostic>> {
+    let mut query = SearchQuery::default();
+    let mut diagnostics = Vec::new();
+
+    for token in tokenize(input, &mut diagnostics) {
+        // Quoted text is never a filter, so `"ext:rs"` searches for the literal text
+        let filter = if token.quoted {
+            None
+        } else {
+            token
+                .text
+                .split_once(':')
+                .filter(|(key, _)| is_filter_key(key))
+        };
+
+        match filter {
+            Some((key, value)) => {
+                match parse_filter(key, value) {
+                    Ok(kind) => query.filters.push(Filter {
+                        negated: token.negated,
+                        kind,
+                    }),
+                    Err(message) => diagnostics.push(QueryDiagnostic {
+                        // Highlight the value rather than the whole token, unless it's missing
+                        start: if value.is_empty() {
+                            token.start
+                        } else {
+                            token.start + usize::from(token.negated) + key.chars().count() + 1
+                        },
+                        end: token.end,
+                        message,
+                    }),
+                }
+            }
+            None if token.negated => query.excluded.push(token.text),
+            None if token.quoted => query.phrases.push(token.text),
+            None => query.terms.push(token.text),
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(query)
+    } else {
+        diagnostics.sort_by_key(|diagnostic| diagnostic.start);
+        Err(diagnostics)
+    }
+}
+
+/// Splits the query on whitespace outside of double quotes
+fn tokenize(input: &str, diagnostics: &mut Vec<QueryDiagnostic>) -> Vec<Token> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    while position < chars.len() {
+        if chars[position].is_whitespace() {
+            position += 1;
+            continue;
+        }
+
+        let start = position;
+        let negated = chars[position] == '-'
+            && chars
+                .get(position + 1)
+                .is_some_and(|next| !next.is_whitespace());
+        if negated {
+            position += 1;
+        }
+
+        let mut text = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        while position < chars.len() && (in_quotes || !chars[position].is_whitespace()) {
+            if chars[position] == '"' {
+                in_quotes = !in_quotes;
+                quoted = true;
+            } else {
+                text.push(chars[position]);
+            }
+            position += 1;
+        }
+
+        if in_quotes {
+            diagnostics.push(QueryDiagnostic {
+                start,
+                end: position,
+                message: "Missing closing quote".to_string(),
+            });
+        }
+        if !text.is_empty() {
+            tokens.push(Token {
+                text,
+                start,
+                end: position,
+                negated,
+                quoted,
+            });
+        }
+    }
+
+    tokens
+}
+
+fn is_filter_key(key: &str) -> bool {
+    matches!(
+        key.to_lowercase().as_str(),
+        "ext" | "size" | "modified" | "created" | "path" | "type"
+    )
+}
+
+/// Parses the value of a filter, returning a message describing the problem on failure
+fn parse_filter(key: &str, value: &str) -> Result<FilterKind, String> {
+    if value.is_empty() {
+        return Err(format!("Missing value for {}:", key));
+    }
+
+    match key.to_lowercase().as_str() {
+        "ext" => Ok(FilterKind::Extension(
+            value
+                .split(',')
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                .filter(|ext| !ext.is_empty())
+                .collect(),
+        )),
+        "size" => {
+            let (comparison, amount) = split_comparison(value, Comparison::GreaterOrEqual);
+            let bytes = parse_amount(amount, &SIZE_UNITS, 1)
+                .ok_or_else(|| format!("Invalid size \"{}\", expected e.g. >10MB", value))?;
+            Ok(FilterKind::Size(comparison, bytes))
+        }
+        "modified" | "created" => {
+            let (comparison, amount) = split_comparison(value, Comparison::Less);
+            let seconds = parse_amount(amount, &AGE_UNITS, 24 * 60 * 60)
+                .ok_or_else(|| format!("Invalid age \"{}\", expected e.g. <7d", value))?;
+            let age = Duration::from_secs(seconds);
+            if key.eq_ignore_ascii_case("modified") {
+                Ok(FilterKind::Modified(comparison, age))
+            } else {
+                Ok(FilterKind::Created(comparison, age))
+            }
+        }
+        "path" => Ok(FilterKind::Path(value.to_lowercase())),
+        "type" => match value.to_lowercase().as_str() {
+            "file" | "f" => Ok(FilterKind::Type("file".to_string())),
+            "dir" | "directory" | "folder" | "d" => Ok(FilterKind::Type("directory".to_string())),
+            _ => Err(format!("Unknown type \"{}\", expected file or dir", value)),
+        },
+        _ => Err(format!("Unknown filter {}:", key)),
+    }
+}
+
+/// Splits a leading comparison operator from the value, falling back to the default of
+/// the filter. `=` still asks for the exact value.
+fn split_comparison(value: &str, default: Comparison) -> (Comparison, &str) {
+    for (operator, comparison) in [
+        (">=", Comparison::GreaterOrEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">", Comparison::Greater),
+        ("<", Comparison::Less),
+        ("=", Comparison::Equal),
+    ] {
+        if let Some(rest) = value.strip_prefix(operator) {
+            return (comparison, rest);
+        }
+    }
+    (default, value)
+}
+
+/// Parses a number followed by an optional unit, such as `1.5GB` or `7d`
+fn parse_amount(amount: &str, units: &[(&str, u64)], default_unit: u64) -> Option<u64> {
+    let split = amount
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(amount.len());
+    let (number, unit) = amount.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let multiplier = if unit.is_empty() {
+        default_unit
+    } else {
+        let unit = unit.to_lowercase();
+        units
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, multiplier)| *multiplier)?
+    };
+
+    Some((number * multiplier as f64) as u64)
+}
+
+//// Matching
+impl Comparison {
+    fn compare<T: PartialOrd>(self, actual: T, expected: T) -> bool {
+        match self {
+            Comparison::Less => actual < expected,
+            Comparison::LessOrEqual => actual <= expected,
+            Comparison::Greater => actual > expected,
+            Comparison::GreaterOrEqual => actual >= expected,
+            Comparison::Equal => actual == expected,
+        }
+    }
+}
+
+impl Filter {
+    /// Returns true if the entry passes the filter
+    pub fn matches(&self, details: &FileView, now: SystemTime) -> bool {
+        let age = |date: Option<SystemTime>| date.and_then(|date| now.duration_since(date).ok());
+
+        let matched = match &self.kind {
+            FilterKind::Extension(extensions) => extensions
+                .iter()
+                .any(|ext| details.file_extension.eq_ignore_ascii_case(ext)),
+            FilterKind::Size(comparison, bytes) => comparison.compare(details.file_size, *bytes),
+            FilterKind::Modified(comparison, expected) => age(details.modified_date)
+                .is_some_and(|actual| comparison.compare(actual, *expected)),
+            FilterKind::Created(comparison, expected) => age(details.creation_date)
+                .is_some_and(|actual| comparison.compare(actual, *expected)),
+            FilterKind::Path(fragment) => details.file_path.to_lowercase().contains(fragment),
+            FilterKind::Type(file_type) => details.file_type == file_type,
+        };
+
+        matched != self.negated
+    }
+}
+
+impl SearchQuery {
+    /// Folds the terms, phrases and exclusions the way names are folded when matching
+    pub fn set_folding(&mut self, folding: Folding) {
+        self.folding = folding;
+        for term in &mut self.terms {
+            *term = folding.fold(term).into_owned();
+        }
+        for text in self.phrases.iter_mut().chain(&mut self.excluded) {
+            *text = folding.key(text);
+        }
+    }
+
+    /// Returns true if the entry passes every filter, phrase and exclusion, given its file
+    /// name folded like the query. Fuzzy terms are scored separately.
+    pub fn accepts(&self, details: &FileView, folded_name: &str, now: SystemTime) -> bool {
+        if details.ignored && !self.include_ignored {
+            return false;
+        }
+        if !self
+            .filters
+            .iter()
+            .all(|filter| filter.matches(details, now))
+        {
+            return false;
+        }
+        if self.phrases.is_empty() && self.excluded.is_empty() {
+            return true;
+        }
+
+        // Folding keeps ASCII as it is, the phrases are lowercase unless case matters
+        let file_name = if self.folding.case_sensitive {
+            Cow::Borrowed(folded_name)
+        } else {
+            Cow::Owned(folded_name.to_ascii_lowercase())
+        };
+        self.phrases.iter().all(|phrase| file_name.contains(phrase))
+            && !self.excluded.iter().any(|word| file_name.contains(word))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the only filter of a query that must parse
+    fn only_filter(input: &str) -> Filter {
+        let query = parse_query(input).unwrap();
+        assert_eq!(query.filters.len(), 1);
+        query.filters[0].clone()
+    }
+
+    #[test]
+    fn tokens_are_sorted_into_terms_phrases_and_exclusions() {
+        let query = parse_query(r#"report -draft "final copy" "ext:rs""#).unwrap();
+        assert_eq!(query.terms, ["report"]);
+        assert_eq!(query.phrases, ["final copy", "ext:rs"]);
+        assert_eq!(query.excluded, ["draft"]);
+        assert!(query.filters.is_empty());
+    }
+
+    #[test]
+    fn filters_are_parsed() {
+        let query = parse_query("ext:.RS,toml -path:Target type:dir").unwrap();
+        assert!(query.terms.is_empty());
+        assert!(matches!(
+            &query.filters[0].kind,
+            FilterKind::Extension(extensions) if extensions == &["rs", "toml"]
+        ));
+        assert!(query.filters[1].negated);
+        assert!(matches!(&query.filters[1].kind, FilterKind::Path(path) if path == "target"));
+        assert!(matches!(&query.filters[2].kind, FilterKind::Type(kind) if kind == "directory"));
+    }
+
+    #[test]
+    fn sizes_and_ages_use_their_operators_and_units() {
+        assert!(matches!(
+            only_filter("size:>1.5kb").kind,
+            FilterKind::Size(Comparison::Greater, 1536)
+        ));
+        assert!(matches!(
+            only_filter("size:=100").kind,
+            FilterKind::Size(Comparison::Equal, 100)
+        ));
+        assert!(matches!(
+            only_filter("created:>=2w").kind,
+            FilterKind::Created(Comparison::GreaterOrEqual, age) if age.as_secs() == 14 * 24 * 60 * 60
+        ));
+    }
+
+    #[test]
+    fn missing_operators_default_per_filter() {
+        assert!(matches!(
+            only_filter("size:10MB").kind,
+            FilterKind::Size(Comparison::GreaterOrEqual, bytes) if bytes == 10 << 20
+        ));
+        assert!(matches!(
+            only_filter("modified:7d").kind,
+            FilterKind::Modified(Comparison::Less, age) if age.as_secs() == 7 * 24 * 60 * 60
+        ));
+    }
+
+    #[test]
+    fn invalid_values_are_reported_on_the_value() {
+        let diagnostics = parse_query("notes size:big -type:link").unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!((diagnostics[0].start, diagnostics[0].end), (11, 14));
+        assert_eq!((diagnostics[1].start, diagnostics[1].end), (21, 25));
+    }
+
+    #[test]
+    fn missing_values_are_reported_on_the_key() {
+        let diagnostics = parse_query("notes ext:").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].start, diagnostics[0].end), (6, 10));
+        assert_eq!(diagnostics[0].message, "Missing value for ext:");
+    }
+
+    #[test]
+    fn unclosed_quotes_are_reported() {
+        let diagnostics = parse_query(r#"a "open phrase"#).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].start, diagnostics[0].end), (2, 14));
+    }
+}
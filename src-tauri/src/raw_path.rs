@@ -0,0 +1,142 @@
+//// Raw paths
+// File names don't have to be valid Unicode: on Linux they are arbitrary bytes, often
+// Latin-1 from old archives or SMB shares, and on Windows they can hold unpaired
+// surrogates. Such entries are indexed and searched under a display path that marks each
+// invalid byte (or unpaired surrogate) with U+FFFD followed by its value in hex, and keep
+// the exact path as bytes so they can still be opened and walked again. A literal U+FFFD
+// is doubled, so two different paths never share a display path.
+
+//// Imports
+use std::path::{Path, PathBuf};
+
+//// Conversions
+/// Marks an escaped byte or unit in a display path
+const MARKER: char = '\u{FFFD}';
+
+/// Returns the exact bytes of a path, or None if its display path already is the exact path
+pub fn raw_bytes(path: &Path) -> Option<Vec<u8>> {
+    if path.to_str().is_some_and(|text| !text.contains(MARKER)) {
+        return None;
+    }
+    Some(platform::to_bytes(path))
+}
+
+/// Rebuilds the exact path from the bytes of raw_bytes
+pub fn from_bytes(bytes: &[u8]) -> PathBuf {
+    platform::from_bytes(bytes)
+}
+
+/// Display path used as the index key: the path itself when it is valid Unicode, with
+/// invalid parts escaped otherwise
+pub fn display(path: &Path) -> String {
+    match path.to_str() {
+        Some(text) if !text.contains(MARKER) => text.to_string(),
+        _ => platform::escape(path),
+    }
+}
+
+/// Pushes a valid character, doubling the marker
+fn push_char(display: &mut String, character: char) {
+    display.push(character);
+    if character == MARKER {
+        display.push(MARKER);
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::path::{Path, PathBuf};
+
+    pub fn to_bytes(path: &Path) -> Vec<u8> {
+        path.as_os_str().as_bytes().to_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> PathBuf {
+        PathBuf::from(OsString::from_vec(bytes.to_vec()))
+    }
+
+    pub fn escape(path: &Path) -> String {
+        let bytes = path.as_os_str().as_bytes();
+        let mut display = String::with_capacity(bytes.len());
+        for chunk in bytes.utf8_chunks() {
+            chunk
+                .valid()
+                .chars()
+                .for_each(|character| super::push_char(&mut display, character));
+            for byte in chunk.invalid() {
+                display.push(super::MARKER);
+                display.push_str(&format!("{:02X}", byte));
+            }
+        }
+        display
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use std::path::{Path, PathBuf};
+
+    // Windows paths are sequences of 16-bit units, stored little-endian
+    pub fn to_bytes(path: &Path) -> Vec<u8> {
+        path.as_os_str()
+            .encode_wide()
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> PathBuf {
+        let wide: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        PathBuf::from(OsString::from_wide(&wide))
+    }
+
+    pub fn escape(path: &Path) -> String {
+        let mut display = String::new();
+        for unit in char::decode_utf16(path.as_os_str().encode_wide()) {
+            match unit {
+                Ok(character) => super::push_char(&mut display, character),
+                Err(error) => {
+                    display.push(super::MARKER);
+                    display.push_str(&format!("{:04X}", error.unpaired_surrogate()));
+                }
+            }
+        }
+        display
+    }
+}
+
+//// Serialization
+/// Serde adapter for paths that may not be valid Unicode. They are written as a string
+/// when possible and as their raw bytes otherwise, where a PathBuf would fail to serialize.
+pub mod serde_path {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::path::{Path, PathBuf};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum StoredPath {
+        Text(String),
+        Raw(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match super::raw_bytes(path) {
+            Some(bytes) => StoredPath::Raw(bytes),
+            None => StoredPath::Text(path.display().to_string()),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Ok(match StoredPath::deserialize(deserializer)? {
+            StoredPath::Text(text) => PathBuf::from(text),
+            StoredPath::Raw(bytes) => super::from_bytes(&bytes),
+        })
+    }
+}
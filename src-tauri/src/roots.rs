@@ -0,0 +1,226 @@
+//// Index roots
+// Every root folder is indexed with its own exclusion rules and reindex schedule.
+// All roots share the same index, and roots may not be nested inside each other
+// so every indexed path belongs to exactly one root.
+
+//// Imports
+use crate::exclusions::ExclusionRules;
+use crate::jobs::{self, JobState};
+use crate::{create_index, get_roots, FILE_INDEX};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tauri::api::path::config_dir;
+use tauri::Window;
+
+//// Constants
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+//// Global Variables
+// When each root was last indexed, used to find the roots due for a scheduled reindex
+static LAST_INDEXED: Lazy<std::sync::Mutex<HashMap<String, Instant>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+static SCHEDULER_STARTED: AtomicBool = AtomicBool::new(false);
+
+//// Data Structures
+/// Settings of one indexed folder, as saved in the setup file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RootConfig {
+    pub path: String,
+    #[serde(default)]
+    pub exclusions: ExclusionRules,
+    #[serde(default)]
+    pub reindex_interval_minutes: Option<u64>, // Rescan on top of the watcher, None disables it
+}
+
+//// Root Lookup and Validation
+impl RootConfig {
+    pub fn new(path: String, exclusions: ExclusionRules) -> Self {
+        RootConfig {
+            path,
+            exclusions,
+            reindex_interval_minutes: None,
+        }
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.path)
+    }
+}
+
+/// Returns the root that contains the path
+pub fn root_for_path<'a>(roots: &'a [RootConfig], path: &Path) -> Option<&'a RootConfig> {
+    roots.iter().find(|root| root.contains(path))
+}
+
+/// Checks that a root can be added next to the others, replacing a root with the same path
+pub fn validate_root(roots: &[RootConfig], candidate: &RootConfig) -> Result<(), String> {
+    let candidate_path = Path::new(&candidate.path);
+    if !candidate_path.is_dir() {
+        return Err(format!("{} is not a folder", candidate.path));
+    }
+    candidate.exclusions.compile()?;
+
+    for root in roots {
+        let root_path = Path::new(&root.path);
+        if root_path == candidate_path {
+            continue;
+        }
+        if candidate_path.starts_with(root_path) || root_path.starts_with(candidate_path) {
+            return Err(format!(
+                "{} overlaps with the index root {}",
+                candidate.path, root.path
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the root with the same path, or adds the root at the end
+pub fn upsert_root(roots: &mut Vec<RootConfig>, root: RootConfig) {
+    match roots
+        .iter_mut()
+        .find(|existing| Path::new(&existing.path) == Path::new(&root.path))
+    {
+        Some(existing) => *existing = root,
+        None => roots.push(root),
+    }
+}
+
+/// Makes the folder the setup root, which is the first root while add_root manages the
+/// others. A root of the same folder keeps its settings, its exclusion rules only change
+/// if new ones are given. Returns the root that was replaced, if it was another folder.
+pub fn replace_setup_root(
+    roots: &mut Vec<RootConfig>,
+    path: String,
+    exclusions: Option<ExclusionRules>,
+) -> Result<Option<RootConfig>, String> {
+    let folder = PathBuf::from(&path);
+    let same_folder = |root: &RootConfig| Path::new(&root.path) == folder;
+    let mut others = roots.clone();
+    let previous = (!others.is_empty()).then(|| others.remove(0));
+    let existing = match &previous {
+        Some(root) if same_folder(root) => previous.clone(),
+        _ => others
+            .iter()
+            .position(same_folder)
+            .map(|position| others.remove(position)),
+    };
+    let replaced = previous.filter(|root| !same_folder(root));
+
+    let root = RootConfig {
+        path,
+        exclusions: exclusions
+            .or_else(|| existing.as_ref().map(|root| root.exclusions.clone()))
+            .unwrap_or_default(),
+        reindex_interval_minutes: existing.and_then(|root| root.reindex_interval_minutes),
+    };
+    validate_root(&others, &root)?;
+    others.insert(0, root);
+    *roots = others;
+    Ok(replaced)
+}
+
+//// Scheduled Reindexing
+/// Records that the given roots were just indexed
+pub fn mark_indexed(roots: &[RootConfig]) {
+    let mut last_indexed = LAST_INDEXED.lock().unwrap();
+    for root in roots {
+        last_indexed.insert(root.path.clone(), Instant::now());
+    }
+}
+
+/// Starts the background task that reindexes the roots with a schedule, once per run
+pub fn start_scheduler(window: Window) {
+    if SCHEDULER_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(SCHEDULE_CHECK_INTERVAL).await;
+
+            // Leave running jobs alone and don't continue a paused one behind the user's back
+            if jobs::state().await != JobState::Idle {
+                continue;
+            }
+
+            let roots = get_roots().await.unwrap_or_default();
+            let due = {
+                let last_indexed = LAST_INDEXED.lock().unwrap();
+                roots.iter().any(|root| {
+                    let Some(minutes) = root.reindex_interval_minutes else {
+                        return false;
+                    };
+                    last_indexed
+                        .get(&root.path)
+                        .is_none_or(|last| last.elapsed() >= Duration::from_secs(minutes * 60))
+                })
+            };
+            if !due {
+                continue;
+            }
+
+            // Unchanged directories are skipped, so rescanning the other roots is cheap
+            println!("Scheduled reindex is due. Rescanning...");
+            let Some(file_index_path) = config_dir().map(|dir| dir.join(FILE_INDEX)) else {
+                continue;
+            };
+            if let Err(e) = create_index(window.clone(), file_index_path).await {
+                println!("Error running scheduled reindex: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(path: &Path, reindex_interval_minutes: Option<u64>) -> RootConfig {
+        RootConfig {
+            path: path.display().to_string(),
+            exclusions: ExclusionRules::default(),
+            reindex_interval_minutes,
+        }
+    }
+
+    #[test]
+    fn setup_replaces_only_the_first_root() {
+        let folder = std::env::temp_dir().join(format!("setup-roots-{}", std::process::id()));
+        let (first, child, added) = (
+            folder.join("first"),
+            folder.join("first/child"),
+            folder.join("added"),
+        );
+        std::fs::create_dir_all(&child).unwrap();
+        std::fs::create_dir_all(&added).unwrap();
+        let mut roots = vec![root(&first, Some(60)), root(&added, Some(5))];
+
+        // The same folder keeps its schedule
+        let same = replace_setup_root(&mut roots, first.display().to_string(), None);
+        let kept = roots[0].reindex_interval_minutes;
+
+        // A folder inside the setup root replaces it instead of overlapping with it
+        let inside = replace_setup_root(&mut roots, child.display().to_string(), None);
+        let paths: Vec<String> = roots.iter().map(|root| root.path.clone()).collect();
+
+        // An added root becomes the setup root along with its settings
+        let moved = replace_setup_root(&mut roots, added.display().to_string(), None);
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        assert!(same.unwrap().is_none());
+        assert_eq!(kept, Some(60));
+        assert_eq!(inside.unwrap().unwrap().path, first.display().to_string());
+        assert_eq!(
+            paths,
+            [child.display().to_string(), added.display().to_string()]
+        );
+        assert_eq!(moved.unwrap().unwrap().path, child.display().to_string());
+        assert_eq!(roots.len(), 1);
+        assert_eq!(roots[0].reindex_interval_minutes, Some(5));
+    }
+}
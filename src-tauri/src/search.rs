@@ -0,0 +1,555 @@
+//// Search
+// Scores the entries of a CompactIndex against a parsed query. Entries containing the
+// query are found through the trigram indexes and scored first, the fuzzy matcher only
+// looks further when they are too few. Scoring runs on a long-lived Rayon pool and stops
+// as soon as a newer search starts. Nothing here depends on Tauri, so the search benchmark
+// runs this same code.
+
+//// Imports
+use crate::compact_index::{CompactIndex, FileView};
+use crate::normalize::Folding;
+use crate::query::SearchQuery;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use once_cell::sync::Lazy;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::slice::ParallelSliceMut;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+//// Constants
+const MINIMUM_SCORE: i64 = 20;
+const STEM_WEIGHT: i64 = 2; // Matches in the file stem count more than matches in the path
+pub const FUZZY_FALLBACK_LIMIT: usize = 20; // Fewer substring matches than this also scan for fuzzy ones
+const SEARCH_CHUNK_SIZE: usize = 50_000; // Entries stream_search scores between batches
+
+//// Global Variables
+// Long-lived pool for scoring search candidates, sized by the search_threads setting
+static SEARCH_POOL: Lazy<RwLock<Arc<rayon::ThreadPool>>> = Lazy::new(|| {
+    let pool = build_search_pool(num_cpus::get()).expect("default search pool can be built");
+    RwLock::new(Arc::new(pool))
+});
+static MATCHER: Lazy<SkimMatcherV2> = Lazy::new(|| SkimMatcherV2::default().ignore_case());
+static CASE_SENSITIVE_MATCHER: Lazy<SkimMatcherV2> =
+    Lazy::new(|| SkimMatcherV2::default().respect_case());
+// Generation of the latest search, every older search is cancelled
+static SEARCH_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+//// Data Structures
+/// Everything besides the query that affects the ranking of a match
+pub struct Ranking {
+    pub extensions: HashMap<String, i64>, // Boost per preferred extension
+    pub frecency: HashMap<String, i64>,   // Boost per recently opened file
+    pub now: SystemTime,
+}
+
+/// Cancellation token of a search, taken when the search starts
+#[derive(Debug, Clone, Copy)]
+pub struct SearchToken(u64);
+
+/// Order of the results of search_files
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    #[default]
+    Score, // Best matches first
+    Name,      // A to Z, ignoring case
+    Path,      // A to Z
+    Size,      // Largest first
+    Date,      // Most recently modified first
+    Extension, // A to Z, then by name
+}
+
+/// Character ranges of a text, end exclusive
+pub type MatchRanges = Vec<(usize, usize)>;
+
+impl SearchToken {
+    /// Starts a new search, cancelling every search that is still running
+    pub fn start() -> Self {
+        SearchToken(SEARCH_GENERATION.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    pub fn is_cancelled(self) -> bool {
+        SEARCH_GENERATION.load(Ordering::Relaxed) != self.0
+    }
+}
+
+//// Global Variables Getter and Setters
+/// Builds the thread pool used by search_files
+fn build_search_pool(threads: usize) -> Result<rayon::ThreadPool, String> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|index| format!("search-{}", index))
+        .build()
+        .map_err(|e| format!("Failed to build Rayon thread pool: {}", e))
+}
+
+// Function to resize the search pool, using every core if no thread count is set
+pub fn set_search_threads(threads: Option<usize>) -> Result<(), String> {
+    let threads = threads
+        .filter(|threads| *threads > 0)
+        .unwrap_or_else(num_cpus::get);
+    let mut pool = SEARCH_POOL.write().unwrap();
+    if pool.current_num_threads() != threads {
+        *pool = Arc::new(build_search_pool(threads)?);
+    }
+    Ok(())
+}
+
+// Function to get the current search pool
+pub fn get_search_pool() -> Arc<rayon::ThreadPool> {
+    SEARCH_POOL.read().unwrap().clone()
+}
+
+//// Scoring
+/// Scores a file by fuzzy matching the query against its stem and its full path, both
+/// folded the same way as the query
+fn score_filename(file_name: &str, file_path: &str, query: &str, folding: Folding) -> Option<i64> {
+    let matcher = matcher_for(folding);
+    let stem_score = matcher
+        .fuzzy_match(file_stem(file_name), query)
+        .map(|score| score * STEM_WEIGHT);
+    let path_score = matcher.fuzzy_match(file_path, query);
+
+    // Keep the better of the two matches, dropping weak ones
+    stem_score
+        .max(path_score)
+        .filter(|score| *score >= MINIMUM_SCORE)
+}
+
+/// Returns the fuzzy matcher for folded text, which only minds ASCII case if asked to
+fn matcher_for(folding: Folding) -> &'static SkimMatcherV2 {
+    if folding.case_sensitive {
+        &CASE_SENSITIVE_MATCHER
+    } else {
+        &MATCHER
+    }
+}
+
+/// Returns the file name without its extension
+fn file_stem(file_name: &str) -> &str {
+    Path::new(file_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(file_name)
+}
+
+/// Merges character indices into sorted ranges, end exclusive
+fn to_ranges(mut indices: Vec<usize>) -> MatchRanges {
+    indices.sort_unstable();
+    indices.dedup();
+    let mut ranges = MatchRanges::new();
+    for index in indices {
+        match ranges.last_mut() {
+            Some((_, end)) if *end == index => *end += 1,
+            _ => ranges.push((index, index + 1)),
+        }
+    }
+    ranges
+}
+
+/// Finds the characters of the file name and path that matched the query, taking each
+/// term from the stem or the path like score_filename does. The file name ends the path,
+/// so characters matched in one are also marked in the other. Returns character ranges
+/// of the original text, also where folding changed the number of characters.
+pub fn match_ranges(
+    file_name: &str,
+    file_path: &str,
+    query: &SearchQuery,
+) -> (MatchRanges, MatchRanges) {
+    let folding = query.folding;
+    let matcher = matcher_for(folding);
+    let file_stem = folding.fold_mapped(file_stem(file_name));
+    let folded_path = folding.fold_mapped(file_path);
+    let name_offset = file_path
+        .chars()
+        .count()
+        .saturating_sub(file_name.chars().count());
+    let mut name_indices = Vec::new();
+    let mut path_indices = Vec::new();
+
+    for term in &query.terms {
+        let stem_match = matcher
+            .fuzzy_indices(&file_stem.text, term)
+            .map(|(score, indices)| (score * STEM_WEIGHT, indices));
+        let path_match = matcher.fuzzy_indices(&folded_path.text, term);
+        match (stem_match, path_match) {
+            (Some((stem_score, indices)), path_match)
+                if path_match
+                    .as_ref()
+                    .is_none_or(|(path_score, _)| stem_score >= *path_score) =>
+            {
+                let indices = file_stem.source_indices(&indices);
+                path_indices.extend(indices.iter().map(|index| index + name_offset));
+                name_indices.extend(indices);
+            }
+            (_, Some((_, indices))) => {
+                let indices = folded_path.source_indices(&indices);
+                name_indices.extend(
+                    indices
+                        .iter()
+                        .filter(|index| **index >= name_offset)
+                        .map(|index| index - name_offset),
+                );
+                path_indices.extend(indices);
+            }
+            _ => {}
+        }
+    }
+
+    // Phrases are matched in the file name as is
+    let name_key = folding.key_mapped(file_name);
+    for phrase in &query.phrases {
+        if let Some(start) = name_key.text.find(phrase.as_str()) {
+            let start = name_key.text[..start].chars().count();
+            let folded_indices: Vec<usize> = (start..start + phrase.chars().count()).collect();
+            let phrase_indices = name_key.source_indices(&folded_indices);
+            path_indices.extend(phrase_indices.iter().map(|index| index + name_offset));
+            name_indices.extend(phrase_indices);
+        }
+    }
+
+    (to_ranges(name_indices), to_ranges(path_indices))
+}
+
+/// Returns the ranking boost of an extension, if it is a preferred one
+pub fn extension_weight(file_extension: &str, extensions: &HashMap<String, i64>) -> Option<i64> {
+    if file_extension.is_empty() {
+        return None;
+    }
+    extensions.get(&file_extension.to_lowercase()).copied()
+}
+
+/// Scores an entry against every fuzzy term of the query, returning None if it doesn't match.
+/// The folded name and path are the entry's in the folding of the query. Preferred
+/// extensions and frequently opened files get a boost on top of the match score.
+pub fn score_entry(
+    details: &FileView,
+    (folded_name, folded_path): (&str, &str),
+    query: &SearchQuery,
+    ranking: &Ranking,
+) -> Option<i64> {
+    if !query.accepts(details, folded_name, ranking.now) {
+        return None;
+    }
+
+    let score = query.terms.iter().try_fold(0, |total, term| {
+        Some(total + score_filename(folded_name, folded_path, term, query.folding)?)
+    })?;
+    let extension_boost = extension_weight(details.file_extension, &ranking.extensions);
+    let frecency_boost = ranking.frecency.get(details.file_path).copied();
+    Some(score + extension_boost.unwrap_or(0) + frecency_boost.unwrap_or(0))
+}
+
+/// Scores a single slot, returning its score and the slot if it matches. The buffers hold
+/// the entry's path and its folded path.
+fn match_entry(
+    index: &CompactIndex,
+    slot: u32,
+    (path_buffer, folded_buffer): &mut (String, String),
+    query: &SearchQuery,
+    ranking: &Ranking,
+) -> Option<(i64, u32)> {
+    let entry = &index.entries()[slot as usize];
+    if entry.is_removed() {
+        return None;
+    }
+    // Calculate the similarity score, filtering out non-matching results
+    let details = index.view(entry, path_buffer);
+    let (folded_name, folded_path) = index.folded(slot, query.folding, folded_buffer);
+    score_entry(&details, (folded_name, folded_path), query, ranking).map(|score| (score, slot))
+}
+
+/// Scores the entries in the given slots, or every entry if there are none.
+/// Stops early once the token is cancelled, returning the matches found until then.
+pub fn match_entries(
+    index: &CompactIndex,
+    slots: Option<&[u32]>,
+    query: &SearchQuery,
+    ranking: &Ranking,
+    token: SearchToken,
+) -> Vec<(i64, u32)> {
+    let entries = index.entries();
+    let count = slots.map_or(entries.len(), <[u32]>::len);
+    let scan = |buffers: &mut (String, String), slot: u32| {
+        (!token.is_cancelled()).then(|| match_entry(index, slot, buffers, query, ranking))
+    };
+
+    // Determine if parallel processing is needed
+    if count > 1000 {
+        println!("Parallelizing search with Rayon...");
+        println!("Entries to score: {}", count);
+        let pool = get_search_pool();
+        println!(
+            "Using {} threads for parallel processing.",
+            pool.current_num_threads()
+        );
+
+        pool.install(|| match slots {
+            Some(slots) => slots
+                .par_iter()
+                .map_init(Default::default, |buffers, slot| scan(buffers, *slot))
+                .while_some()
+                .flatten()
+                .collect(),
+            None => (0..entries.len() as u32)
+                .into_par_iter()
+                .map_init(Default::default, scan)
+                .while_some()
+                .flatten()
+                .collect(),
+        })
+    } else {
+        // For small datasets, use sequential iteration
+        println!("Using sequential search...");
+        let mut buffers = Default::default();
+        match slots {
+            Some(slots) => slots
+                .iter()
+                .map_while(|slot| scan(&mut buffers, *slot))
+                .flatten()
+                .collect(),
+            None => (0..entries.len() as u32)
+                .map_while(|slot| scan(&mut buffers, slot))
+                .flatten()
+                .collect(),
+        }
+    }
+}
+
+/// Scores the slots, or every entry if there are none, in chunks of SEARCH_CHUNK_SIZE.
+/// Adds the matches and calls on_chunk with all matches so far after every chunk.
+/// Returns false if the token was cancelled before all slots were scored.
+fn match_in_chunks(
+    index: &CompactIndex,
+    slots: Option<&[u32]>,
+    query: &SearchQuery,
+    ranking: &Ranking,
+    token: SearchToken,
+    matches: &mut Vec<(i64, u32)>,
+    on_chunk: &mut impl FnMut(&[(i64, u32)]),
+) -> bool {
+    let count = slots.map_or(index.entries().len(), <[u32]>::len);
+    for start in (0..count).step_by(SEARCH_CHUNK_SIZE) {
+        let end = (start + SEARCH_CHUNK_SIZE).min(count);
+        let chunk: Vec<u32> = match slots {
+            Some(slots) => slots[start..end].to_vec(),
+            None => (start as u32..end as u32).collect(),
+        };
+        matches.extend(match_entries(index, Some(&chunk), query, ranking, token));
+        if token.is_cancelled() {
+            return false;
+        }
+        on_chunk(matches);
+    }
+    true
+}
+
+/// Scores the entries containing the query first, and if that finds fewer than
+/// FUZZY_FALLBACK_LIMIT matches, the other entries fallback_slots bounds the fuzzy search
+/// to. Calls on_chunk like match_in_chunks. Returns the matches unsorted, or None if the
+/// token was cancelled before all of them were scored.
+pub fn find_matches(
+    index: &CompactIndex,
+    query: &SearchQuery,
+    ranking: &Ranking,
+    token: SearchToken,
+    on_chunk: &mut impl FnMut(&[(i64, u32)]),
+) -> Option<Vec<(i64, u32)>> {
+    let candidates = candidate_slots(index, query);
+    if let Some(candidates) = &candidates {
+        println!(
+            "Narrowed search to {} of {} entries.",
+            candidates.len(),
+            index.len()
+        );
+    }
+    let mut matches = Vec::new();
+    if !match_in_chunks(
+        index,
+        candidates.as_deref(),
+        query,
+        ranking,
+        token,
+        &mut matches,
+        on_chunk,
+    ) {
+        return None;
+    }
+    let Some(candidates) =
+        candidates.filter(|_| !query.terms.is_empty() && matches.len() < FUZZY_FALLBACK_LIMIT)
+    else {
+        return Some(matches);
+    };
+
+    // Fuzzy matches among the rest, which cover the whole index unless phrases bound them
+    let is_rest = |slot: &u32| candidates.binary_search(slot).is_err();
+    let rest: Vec<u32> = match fallback_slots(index, query) {
+        Some(fallback) => fallback.into_iter().filter(is_rest).collect(),
+        None => (0..index.entries().len() as u32).filter(is_rest).collect(),
+    };
+    let finished = match_in_chunks(
+        index,
+        Some(&rest),
+        query,
+        ranking,
+        token,
+        &mut matches,
+        on_chunk,
+    );
+    finished.then_some(matches)
+}
+
+/// Returns the first limit matches in the given order without sorting all of them
+pub fn top_matches(
+    index: &CompactIndex,
+    matches: &[(i64, u32)],
+    sort: SortBy,
+    limit: usize,
+) -> Vec<(i64, u32)> {
+    let mut top = matches.to_vec();
+    if sort == SortBy::Score && top.len() > limit {
+        top.select_nth_unstable_by_key(limit, |(score, _)| Reverse(*score));
+        top.truncate(limit);
+    }
+    sort_matches(index, &mut top, sort);
+    top.truncate(limit);
+    top
+}
+
+/// Builds the sort key of a slot from its view, followed by its full path
+fn sort_key<K>(index: &CompactIndex, slot: u32, key: impl Fn(&FileView) -> K) -> (K, String) {
+    let mut path_buffer = String::new();
+    let details = index.view(&index.entries()[slot as usize], &mut path_buffer);
+    (key(&details), path_buffer)
+}
+
+/// Sorts matches in the given order. Ties are broken by path, so pages of the same
+/// search never overlap.
+pub fn sort_matches(index: &CompactIndex, matches: &mut [(i64, u32)], sort: SortBy) {
+    get_search_pool().install(|| match sort {
+        // Shorter paths first among equally good matches
+        SortBy::Score => matches.par_sort_by_cached_key(|(score, slot)| {
+            let (_, path) = sort_key(index, *slot, |_| ());
+            (Reverse(*score), path.len(), path)
+        }),
+        SortBy::Name => matches.par_sort_by_cached_key(|(_, slot)| {
+            sort_key(index, *slot, |details| details.file_name.to_lowercase())
+        }),
+        SortBy::Path => matches.par_sort_by_cached_key(|(_, slot)| sort_key(index, *slot, |_| ())),
+        SortBy::Size => matches.par_sort_by_cached_key(|(_, slot)| {
+            sort_key(index, *slot, |details| Reverse(details.file_size))
+        }),
+        SortBy::Date => matches.par_sort_by_cached_key(|(_, slot)| {
+            sort_key(index, *slot, |details| Reverse(details.modified_date))
+        }),
+        SortBy::Extension => matches.par_sort_by_cached_key(|(_, slot)| {
+            sort_key(index, *slot, |details| {
+                (
+                    details.file_extension.to_lowercase(),
+                    details.file_name.to_lowercase(),
+                )
+            })
+        }),
+    });
+}
+
+/// Narrows a search to the entries containing every fuzzy term and phrase of the query
+/// as a substring. Returns None if none of them is long enough to narrow by.
+fn candidate_slots(index: &CompactIndex, query: &SearchQuery) -> Option<Vec<u32>> {
+    let terms = query.terms.iter().map(|term| (term, false));
+    let phrases = query.phrases.iter().map(|phrase| (phrase, true));
+    terms
+        .chain(phrases)
+        .filter_map(|(text, names_only)| index.substring_candidates(text, names_only))
+        .reduce(|a, b| intersect_sorted(&a, &b))
+}
+
+/// Bounds the fuzzy fallback of a search to the entries containing every phrase, which
+/// every match has to. Fuzzy terms can't bound it, since an abbreviation like `cfgldr`
+/// matches `config_loader.rs` without sharing a single trigram with it. Returns None if
+/// no phrase is long enough to bound by, so the whole index is scored.
+fn fallback_slots(index: &CompactIndex, query: &SearchQuery) -> Option<Vec<u32>> {
+    query
+        .phrases
+        .iter()
+        .filter_map(|phrase| index.substring_candidates(phrase, true))
+        .reduce(|a, b| intersect_sorted(&a, &b))
+}
+
+/// Returns the values present in both sorted lists
+fn intersect_sorted(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut intersection = Vec::with_capacity(a.len().min(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                intersection.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    intersection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::parse_query;
+    use crate::FileDetails;
+
+    fn file(file_path: &str) -> FileDetails {
+        let file_name = file_path.rsplit('/').next().unwrap();
+        FileDetails {
+            file_name: file_name.to_string(),
+            file_path: file_path.to_string(),
+            file_size: 42,
+            file_type: "file".to_string(),
+            creation_date: None,
+            modified_date: None,
+            file_extension: file_name.rsplit('.').next().unwrap().to_string(),
+            ignored: false,
+            scanned: false,
+            raw_path: None,
+        }
+    }
+
+    #[test]
+    fn abbreviations_find_entries_sharing_no_trigram() {
+        let mut index = CompactIndex::default();
+        for file_path in [
+            "/project/src/config_loader.rs",
+            "/project/src/main.rs",
+            "/project/README.md",
+        ] {
+            index.insert(&file(file_path));
+        }
+        let mut query = parse_query("cfgldr").unwrap();
+        query.set_folding(Folding::LOOSE);
+        let ranking = Ranking {
+            extensions: HashMap::new(),
+            frecency: HashMap::new(),
+            now: SystemTime::now(),
+        };
+
+        let matches =
+            find_matches(&index, &query, &ranking, SearchToken::start(), &mut |_| {}).unwrap();
+        let mut path_buffer = String::new();
+        let paths: Vec<String> = matches
+            .iter()
+            .map(|(_, slot)| {
+                let entry = &index.entries()[*slot as usize];
+                index.view(entry, &mut path_buffer).file_path.to_string()
+            })
+            .collect();
+        assert_eq!(paths, ["/project/src/config_loader.rs"]);
+    }
+}
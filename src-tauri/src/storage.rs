@@ -0,0 +1,312 @@
+//// Crash-safe file storage
+// Every file the app owns (the index, the setup, recent files and the usage log) is
+// written next to the target first, flushed to disk and renamed over it, so a crash
+// leaves either the old or the new file but never half of one. The file being replaced
+// is kept as a single backup that is used when the current file turns out to be damaged,
+// but only if it is intact itself, so a damaged file never pushes out a good backup.
+// JSON files start with a line holding the CRC32 of the rest, so a truncated file that
+// still parses is caught as well. Temporary files a crash left behind are removed at
+// startup.
+
+//// Imports
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tauri::Window;
+use tokio::io::AsyncWriteExt;
+
+//// Constants
+const TEMPORARY_SUFFIX: &str = ".tmp";
+const BACKUP_SUFFIX: &str = ".bak";
+const CHECKSUM_PREFIX: &str = "crc32:"; // Starts the first line of JSON files
+
+//// Global Variables
+// Numbers the temporary files, so concurrent writes of the same file never share one
+static NEXT_TEMPORARY: AtomicU64 = AtomicU64::new(0);
+
+//// Data Structures
+/// Problem found while loading a file, reported to the UI
+#[derive(Serialize, Debug, Clone)]
+pub struct StorageIssue {
+    pub file: String,    // Name of the damaged file
+    pub message: String, // What was wrong with it
+    pub recovered: bool, // The backup could be loaded instead
+}
+
+//// Paths
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = OsString::from(path.file_name().unwrap_or_default());
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, BACKUP_SUFFIX)
+}
+
+/// Returns true if the file or its backup exists
+pub fn exists(path: &Path) -> bool {
+    path.exists() || backup_path(path).exists()
+}
+
+/// Returns true if the file name is a temporary file of one of the given files, written
+/// by another process than this one
+fn is_stale_temporary(file_name: &str, file_names: &[&str]) -> bool {
+    let Some(stem) = file_name.strip_suffix(TEMPORARY_SUFFIX) else {
+        return false;
+    };
+    file_names.iter().any(|name| {
+        let Some(numbers) = stem.strip_prefix(name) else {
+            return false;
+        };
+        // Files from before temporary names were numbered end in the suffix alone
+        if numbers.is_empty() {
+            return true;
+        }
+        let mut parts = numbers.strip_prefix('.').unwrap_or_default().split('.');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(process), Some(counter), None) => {
+                counter.parse::<u64>().is_ok()
+                    && process
+                        .parse::<u32>()
+                        .is_ok_and(|process| process != std::process::id())
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Removes the temporary files of the given files that a crash left in the directory
+pub fn remove_temporary_files(directory: &Path, file_names: &[&str]) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        if is_stale_temporary(&file_name.to_string_lossy(), file_names) {
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => println!("Removed leftover {}.", file_name.to_string_lossy()),
+                Err(e) => println!("Failed to remove {}: {}", file_name.to_string_lossy(), e),
+            }
+        }
+    }
+}
+
+//// Checksums
+/// Puts a line with the CRC32 of the JSON in front of it
+fn with_checksum(json: &str) -> String {
+    format!(
+        "{}{:08x}\n{}",
+        CHECKSUM_PREFIX,
+        crc32fast::hash(json.as_bytes()),
+        json
+    )
+}
+
+/// Returns the JSON of a file after checking its checksum. Files written before checksums
+/// were added are plain JSON and are returned as they are.
+fn checked_json(content: &str) -> Result<&str, String> {
+    let Some(rest) = content.strip_prefix(CHECKSUM_PREFIX) else {
+        return Ok(content);
+    };
+    let (checksum, json) = rest
+        .split_once('\n')
+        .ok_or("its checksum line is cut short")?;
+    let checksum = u32::from_str_radix(checksum, 16)
+        .map_err(|_| format!("its checksum \"{}\" is invalid", checksum))?;
+    if crc32fast::hash(json.as_bytes()) != checksum {
+        return Err("its checksum doesn't match".to_string());
+    }
+    Ok(json)
+}
+
+/// Returns true if the file holds JSON that matches its checksum
+pub fn is_intact_json(path: &Path) -> bool {
+    std::fs::read_to_string(path).is_ok_and(|content| {
+        checked_json(&content)
+            .is_ok_and(|json| serde_json::from_str::<serde::de::IgnoredAny>(json).is_ok())
+    })
+}
+
+//// Writing
+/// Replaces the file with the given content. The previous version becomes the backup if
+/// `is_valid` accepts it, otherwise it is overwritten and the older backup stays.
+pub async fn write_atomic(
+    path: &Path,
+    content: &[u8],
+    is_valid: fn(&Path) -> bool,
+) -> Result<(), String> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary_path = with_suffix(
+        path,
+        &format!(
+            ".{}.{}{}",
+            std::process::id(),
+            NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed),
+            TEMPORARY_SUFFIX
+        ),
+    );
+
+    let written = write_temporary(&temporary_path, content).await;
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(&temporary_path).await;
+        return Err(format!("Failed to write {}: {}", file_name, e));
+    }
+
+    // Checking the current file reads all of it, so keep that off the async runtime
+    let current_path = path.to_path_buf();
+    let current_valid =
+        tokio::task::spawn_blocking(move || current_path.exists() && is_valid(&current_path))
+            .await
+            .unwrap_or(false);
+    if current_valid {
+        tokio::fs::rename(path, backup_path(path))
+            .await
+            .map_err(|e| format!("Failed to back up {}: {}", file_name, e))?;
+    } else if path.exists() {
+        println!("{} is damaged, keeping the previous backup.", file_name);
+    }
+    if let Err(e) = tokio::fs::rename(&temporary_path, path).await {
+        let _ = tokio::fs::remove_file(&temporary_path).await;
+        return Err(format!("Failed to replace {}: {}", file_name, e));
+    }
+
+    // Make the renames themselves durable
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        if let Ok(directory) = tokio::fs::File::open(parent).await {
+            let _ = directory.sync_all().await;
+        }
+    }
+    Ok(())
+}
+
+/// Creates the temporary file and flushes the content to disk
+async fn write_temporary(temporary_path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::create(temporary_path).await?;
+    file.write_all(content).await?;
+    file.sync_all().await
+}
+
+/// Serializes the value as pretty JSON and writes it with write_atomic
+pub async fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), String> {
+    write_serialized(path, serde_json::to_string_pretty(value)).await
+}
+
+/// Serializes the value as JSON without whitespace, for large files nobody reads by hand
+pub async fn write_compact_json<T: Serialize + ?Sized>(
+    path: &Path,
+    value: &T,
+) -> Result<(), String> {
+    write_serialized(path, serde_json::to_string(value)).await
+}
+
+async fn write_serialized(path: &Path, json: serde_json::Result<String>) -> Result<(), String> {
+    let json = json.map_err(|e| {
+        format!(
+            "Failed to serialize {}: {}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            e
+        )
+    })?;
+    write_atomic(path, with_checksum(&json).as_bytes(), is_intact_json).await
+}
+
+//// Loading
+/// Reads a JSON file, checks its checksum and parses it
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", file_name, e))?;
+    let json = checked_json(&content).map_err(|e| format!("{} is damaged, {}", file_name, e))?;
+    serde_json::from_str(json).map_err(|e| format!("Failed to parse {}: {}", file_name, e))
+}
+
+/// Loads the file, falling back to its backup if the file is missing or damaged.
+/// Returns the loaded value together with the problem found in the file, if any.
+pub fn load_with_backup<T>(
+    path: &Path,
+    mut load: impl FnMut(&Path) -> Result<T, String>,
+) -> Result<(T, Option<StorageIssue>), StorageIssue> {
+    let message = match load(path) {
+        Ok(value) => return Ok((value, None)),
+        Err(message) => message,
+    };
+
+    let backup = backup_path(path);
+    let recovered = if backup.exists() {
+        load(&backup).ok()
+    } else {
+        None
+    };
+    let issue = StorageIssue {
+        file: path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        message,
+        recovered: recovered.is_some(),
+    };
+    match recovered {
+        Some(value) => Ok((value, Some(issue))),
+        None => Err(issue),
+    }
+}
+
+/// Logs a storage problem and tells the UI about it
+pub fn report_issue(window: &Window, issue: &StorageIssue) {
+    if issue.recovered {
+        println!("{}. Loaded the backup of {}.", issue.message, issue.file);
+    } else {
+        println!("{}. No usable backup of {}.", issue.message, issue.file);
+    }
+    window
+        .emit("storage-error", issue)
+        .unwrap_or_else(|e| println!("Error emitting storage error: {}", e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_catch_json_that_still_parses() {
+        let content = with_checksum("[1,2,3]");
+        assert_eq!(checked_json(&content), Ok("[1,2,3]"));
+
+        let truncated = content.replace("[1,2,3]", "[1,2]");
+        assert!(serde_json::from_str::<Vec<u32>>("[1,2]").is_ok());
+        assert!(checked_json(&truncated).is_err());
+        assert!(checked_json(&content[..content.len() / 2]).is_err());
+
+        // Written before checksums were added
+        assert_eq!(checked_json("[1,2]"), Ok("[1,2]"));
+    }
+
+    #[test]
+    fn only_temporary_files_of_other_processes_are_stale() {
+        let names = ["setup_file.json", "file_index.bin"];
+        let current = format!("setup_file.json.{}.0.tmp", std::process::id());
+        let other = format!("setup_file.json.{}.3.tmp", std::process::id() + 1);
+        assert!(is_stale_temporary(&other, &names));
+        assert!(is_stale_temporary("file_index.bin.tmp", &names));
+        assert!(!is_stale_temporary(&current, &names));
+        assert!(!is_stale_temporary("setup_file.json", &names));
+        assert!(!is_stale_temporary("setup_file.jsonx.1.2.tmp", &names));
+        assert!(!is_stale_temporary("unrelated.json.1.2.tmp", &names));
+    }
+
+    #[tokio::test]
+    async fn json_survives_a_round_trip() {
+        let path = std::env::temp_dir().join(format!("settings-{}.json", std::process::id()));
+        write_json(&path, &vec!["a", "b"]).await.unwrap();
+        let intact = is_intact_json(&path);
+        let read: Result<Vec<String>, String> = read_json(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(intact);
+        assert_eq!(read.unwrap(), ["a", "b"]);
+    }
+}
@@ -0,0 +1,56 @@
+//// Trigram index
+// Inverted index from every run of three characters in a folded text to the ids of the
+// texts containing it. Every substring of three or more characters is found under each of
+// its trigrams, so the shortest posting list among them holds all matches. Callers check
+// those candidates against the real text, which also filters out ids that were removed
+// or reused since they were indexed. CompactIndex rebuilds its trigram indexes once too
+// many of their ids are stale.
+
+//// Imports
+use std::collections::HashMap;
+
+//// Data Structures
+#[derive(Debug, Clone, Default)]
+pub struct TrigramIndex {
+    postings: HashMap<u64, Vec<u32>>, // Maps a packed trigram to the ids containing it
+}
+
+//// Trigrams
+/// Packs three characters into one key, 21 bits each
+fn pack(trigram: &[char]) -> u64 {
+    trigram
+        .iter()
+        .fold(0, |packed, c| (packed << 21) | u64::from(*c))
+}
+
+/// Returns the distinct trigrams of an already folded text
+fn trigrams(text: &str) -> Vec<u64> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut trigrams: Vec<u64> = chars.windows(3).map(pack).collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+//// Index Operations
+impl TrigramIndex {
+    /// Adds an id under every trigram of the folded text
+    pub fn insert(&mut self, text: &str, id: u32) {
+        for trigram in trigrams(text) {
+            self.postings.entry(trigram).or_default().push(id);
+        }
+    }
+
+    /// Returns the ids that may contain the folded text, or None if the text is shorter
+    /// than a trigram and can't be narrowed down. Ids can repeat.
+    pub fn candidates(&self, text: &str) -> Option<&[u32]> {
+        let mut shortest: Option<&[u32]> = None;
+        for trigram in trigrams(text) {
+            let posting = self.postings.get(&trigram).map_or(&[][..], Vec::as_slice);
+            if shortest.is_none_or(|shortest| posting.len() < shortest.len()) {
+                shortest = Some(posting);
+            }
+        }
+        shortest
+    }
+}
@@ -0,0 +1,138 @@
+//// File usage log and frecency ranking
+// Every file opened through open_file is recorded with its timestamp so search can rank
+// files that are opened often and recently above the rest.
+
+//// Imports
+use crate::storage::{self, StorageIssue};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::api::path::config_dir;
+use tauri::Window;
+use tokio::sync::Mutex;
+
+//// Constants
+pub const USAGE_LOG: &str = "usage_log.json";
+const MAX_VISITS_PER_FILE: usize = 20; // Older visits barely count after decaying
+const MAX_TRACKED_FILES: usize = 2000;
+const VISIT_WEIGHT: f64 = 20.0; // Boost of a single visit made right now
+const HALF_LIFE_DAYS: f64 = 7.0; // A visit counts half as much after this many days
+const MAX_FRECENCY_BOOST: i64 = 200; // Keeps usage from outranking much better matches
+
+//// Global Variables
+// Loaded from disk on first use
+static USAGE: Lazy<Mutex<Option<UsageLog>>> = Lazy::new(|| Mutex::new(None));
+
+//// Data Structures
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct UsageLog {
+    files: HashMap<String, Vec<u64>>, // Maps file path to open times in seconds since the epoch
+}
+
+fn usage_log_path() -> Result<PathBuf, String> {
+    Ok(config_dir()
+        .ok_or("Failed to retrieve config directory")?
+        .join(USAGE_LOG))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0)
+}
+
+/// Reads the usage log or its backup, starting empty if neither can be loaded.
+/// Returns the problem found in the file along with the log, if there was one.
+fn read_usage_log() -> (UsageLog, Option<StorageIssue>) {
+    let Ok(path) = usage_log_path() else {
+        return (UsageLog::default(), None);
+    };
+    if !storage::exists(&path) {
+        return (UsageLog::default(), None);
+    }
+    match storage::load_with_backup(&path, storage::read_json) {
+        Ok((log, issue)) => (log, issue),
+        Err(issue) => (UsageLog::default(), Some(issue)),
+    }
+}
+
+/// Reads the usage log at startup, telling the UI if it was damaged
+pub async fn load_usage_log(window: &Window) {
+    let (log, issue) = read_usage_log();
+    if let Some(issue) = issue {
+        storage::report_issue(window, &issue);
+    }
+    *USAGE.lock().await = Some(log);
+}
+
+/// Returns the usage log, reading it from disk if startup hasn't loaded it yet
+async fn loaded(usage: &mut Option<UsageLog>) -> &mut UsageLog {
+    if usage.is_none() {
+        let (log, issue) = read_usage_log();
+        if let Some(issue) = issue {
+            println!("{}. Continuing with what could be loaded.", issue.message);
+        }
+        *usage = Some(log);
+    }
+    usage.as_mut().unwrap()
+}
+
+//// Recording and Scoring
+/// Records that a file was opened now and saves the usage log
+pub async fn record_open(file_path: &str) -> Result<(), String> {
+    let mut usage = USAGE.lock().await;
+    let log = loaded(&mut usage).await;
+
+    let visits = log.files.entry(file_path.to_string()).or_default();
+    visits.push(now_secs());
+    if visits.len() > MAX_VISITS_PER_FILE {
+        visits.drain(..visits.len() - MAX_VISITS_PER_FILE);
+    }
+
+    // Forget the files that haven't been opened for the longest time
+    if log.files.len() > MAX_TRACKED_FILES {
+        let mut last_visits: Vec<(u64, String)> = log
+            .files
+            .iter()
+            .map(|(path, visits)| (visits.last().copied().unwrap_or(0), path.clone()))
+            .collect();
+        last_visits.sort_unstable();
+        for (_, path) in last_visits
+            .into_iter()
+            .take(log.files.len() - MAX_TRACKED_FILES)
+        {
+            log.files.remove(&path);
+        }
+    }
+
+    storage::write_compact_json(&usage_log_path()?, log).await
+}
+
+/// Returns the ranking boost of every file in the usage log.
+/// Each visit adds VISIT_WEIGHT, halved every HALF_LIFE_DAYS since the visit.
+pub async fn frecency_scores() -> HashMap<String, i64> {
+    let mut usage = USAGE.lock().await;
+    let now = now_secs();
+
+    loaded(&mut usage)
+        .await
+        .files
+        .iter()
+        .map(|(path, visits)| {
+            let frecency: f64 = visits
+                .iter()
+                .map(|visit| {
+                    let age_days = now.saturating_sub(*visit) as f64 / 86_400.0;
+                    VISIT_WEIGHT * 0.5_f64.powf(age_days / HALF_LIFE_DAYS)
+                })
+                .sum();
+            (
+                path.clone(),
+                (frecency.round() as i64).min(MAX_FRECENCY_BOOST),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,265 @@
+//// Parallel directory walker
+// Scans every directory of a root as its own rayon task, so wide and deep trees spread
+// over all cores. Each directory reports its changes as a single batch that index_files
+// applies to the index, instead of locking the index for every entry. Once the job is
+// asked to stop, directories that haven't been scanned yet are collected instead, so a
+// paused run can pick them up again.
+
+//// Imports
+use crate::exclusions::CompiledRules;
+use crate::ignore_files::IgnoreRules;
+use crate::jobs::JobControl;
+use crate::progress::PROGRESS;
+use crate::raw_path;
+use crate::{build_file_details, details_unchanged, FileDetails, FileIndex};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::mpsc::UnboundedSender;
+
+//// Data Structures
+/// Directory waiting to be scanned
+pub struct PendingDirectory {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub known_modified: Option<SystemTime>, // Modification time stored in the previous index
+    pub ignore_rules: Arc<IgnoreRules>,     // Ignore patterns of the parent directories
+    pub ignored: bool,                      // The directory itself is ignored
+}
+
+/// Directory left in the queue by a stopped walk, without the ignore rules that are
+/// read from disk again when the walk continues
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueuedDirectory {
+    #[serde(with = "crate::raw_path::serde_path")]
+    pub path: PathBuf,
+    pub depth: usize,
+    pub known_modified: Option<SystemTime>,
+    pub ignored: bool,
+}
+
+/// Changes found in a single directory
+#[derive(Default)]
+pub struct WalkBatch {
+    pub updated: Vec<FileDetails>, // New entries and entries that changed
+    pub removed: Vec<String>,      // Entries that disappeared, along with everything below them
+    pub listed: Option<(String, Option<SystemTime>)>, // The directory and its modification time, if its entries were read
+}
+
+/// State shared by the directory tasks of a walk
+pub struct Walk {
+    pub rules: CompiledRules,
+    pub previous: FileIndex, // Index from the previous run, read only during the walk
+    pub children: HashMap<String, Vec<String>>, // Previously indexed paths by parent directory
+    pub rules_unchanged: bool, // The previous index was built with the same exclusion rules
+    pub sender: UnboundedSender<WalkBatch>,
+    pub unchanged_directories: AtomicUsize,
+    pub control: Arc<JobControl>,
+    pub interrupted: Mutex<Vec<QueuedDirectory>>, // Directories skipped after a stop request
+}
+
+impl PendingDirectory {
+    fn queued(self) -> QueuedDirectory {
+        QueuedDirectory {
+            path: self.path,
+            depth: self.depth,
+            known_modified: self.known_modified,
+            ignored: self.ignored,
+        }
+    }
+}
+
+//// Walking
+impl Walk {
+    /// Walks the directories and everything below them, blocking until every task is done
+    pub fn run(&self, starts: Vec<PendingDirectory>) {
+        rayon::scope(|scope| {
+            for start in starts {
+                self.spawn(scope, start);
+            }
+        });
+    }
+
+    fn spawn<'a>(&'a self, scope: &rayon::Scope<'a>, directory: PendingDirectory) {
+        scope.spawn(move |scope| self.walk_directory(scope, directory));
+    }
+
+    /// Returns the modification time of a directory in the previous index, if the
+    /// previous index holds all of its entries
+    fn known_modified(&self, details: &FileDetails) -> Option<SystemTime> {
+        details
+            .modified_date
+            .filter(|_| details.scanned && self.rules_unchanged)
+    }
+
+    fn send(&self, batch: WalkBatch) {
+        if !batch.updated.is_empty() || !batch.removed.is_empty() || batch.listed.is_some() {
+            // The receiver only goes away if index_files was dropped
+            let _ = self.sender.send(batch);
+        }
+    }
+
+    /// Diffs one directory against the previous index and queues its subdirectories.
+    /// Directories whose modification time matches the previous index are not read again
+    /// if an earlier run read their entries, but their subdirectories are still visited
+    /// since changes deeper down don't touch the modification time of the parents.
+    /// Directories that couldn't be read, lay too deep or weren't reached by a stopped
+    /// run are never flagged as scanned, so the next run reads them.
+    fn walk_directory<'a>(&'a self, scope: &rayon::Scope<'a>, directory: PendingDirectory) {
+        if self.control.is_stopped() {
+            self.interrupted.lock().unwrap().push(directory.queued());
+            return;
+        }
+
+        let PendingDirectory {
+            path: current_path,
+            depth,
+            known_modified,
+            ignore_rules,
+            ignored,
+        } = directory;
+
+        if depth > self.rules.max_depth() {
+            return; // Skip if depth exceeds the allowed depth
+        }
+        PROGRESS.directory_scanned(&current_path);
+
+        let directory_path = raw_path::display(&current_path);
+        let known_children = self
+            .children
+            .get(&directory_path)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let ignore_rules = if self.rules.respect_ignore_files() {
+            IgnoreRules::for_directory(&ignore_rules, &current_path)
+        } else {
+            ignore_rules
+        };
+        let mut batch = WalkBatch::default();
+
+        // Only visit the known subdirectories if nothing was added, removed or renamed here
+        let current_modified = std::fs::metadata(&current_path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if known_modified.is_some() && known_modified == current_modified {
+            self.unchanged_directories.fetch_add(1, Ordering::Relaxed);
+            for child_path in known_children {
+                let Some(details) = self.previous.files.get(child_path) else {
+                    continue;
+                };
+
+                // Ignore files can change without touching the directory
+                let is_dir = details.file_type == "directory";
+                PROGRESS.entry_seen(is_dir, details.file_size);
+                let child_path = details.path();
+                let child_ignored = ignored || ignore_rules.is_ignored(&child_path, is_dir);
+                if details.ignored != child_ignored {
+                    batch.updated.push(FileDetails {
+                        ignored: child_ignored,
+                        ..details.clone()
+                    });
+                }
+
+                if is_dir {
+                    self.spawn(
+                        scope,
+                        PendingDirectory {
+                            path: child_path,
+                            depth: depth + 1,
+                            known_modified: self.known_modified(details),
+                            ignore_rules: ignore_rules.clone(),
+                            ignored: child_ignored,
+                        },
+                    );
+                }
+            }
+            self.send(batch);
+            return;
+        }
+
+        let entries = match std::fs::read_dir(&current_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if e.kind() == std::io::ErrorKind::PermissionDenied {
+                    println!(
+                        "Permission denied for directory: {}. Skipping.",
+                        directory_path
+                    );
+                } else {
+                    println!("Error reading directory {}: {}", directory_path, e);
+                }
+                PROGRESS.error_skipped();
+                return;
+            }
+        };
+
+        let mut seen_paths = HashSet::new();
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            // Names that aren't valid Unicode are indexed under their escaped display
+            // form, build_file_details keeps the exact path
+            let file_name = raw_path::display(Path::new(&entry.file_name()));
+            let file_path = raw_path::display(&entry_path);
+
+            // One metadata call per entry, reused below instead of checking is_dir again
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::PermissionDenied {
+                        println!("Permission denied for accessing: {}. Skipping.", file_path);
+                    } else {
+                        println!("Error getting metadata for {}: {}", file_path, e);
+                    }
+                    PROGRESS.error_skipped();
+                    continue;
+                }
+            };
+
+            // Excluded entries are never seen, so previously indexed ones get removed
+            if self
+                .rules
+                .excludes(&entry_path, metadata.is_dir(), metadata.len())
+            {
+                continue;
+            }
+            PROGRESS.entry_seen(metadata.is_dir(), metadata.len());
+
+            let mut details = build_file_details(&entry_path, file_name, &metadata);
+            details.ignored = ignored || ignore_rules.is_ignored(&entry_path, metadata.is_dir());
+
+            let previous = self.previous.files.get(&file_path);
+            if metadata.is_dir() {
+                self.spawn(
+                    scope,
+                    PendingDirectory {
+                        path: entry_path,
+                        depth: depth + 1,
+                        known_modified: previous
+                            .filter(|old| old.file_type == "directory")
+                            .and_then(|old| self.known_modified(old)),
+                        ignore_rules: ignore_rules.clone(),
+                        ignored: details.ignored,
+                    },
+                );
+            }
+
+            // Only report entries whose size or modification time changed
+            if !previous.is_some_and(|old| details_unchanged(old, &details)) {
+                batch.updated.push(details);
+            }
+            seen_paths.insert(file_path);
+        }
+
+        // Drop entries that disappeared since the previous index
+        batch.removed = known_children
+            .iter()
+            .filter(|child_path| !seen_paths.contains(*child_path))
+            .cloned()
+            .collect();
+        batch.listed = Some((directory_path, current_modified));
+        self.send(batch);
+    }
+}
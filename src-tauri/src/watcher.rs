@@ -0,0 +1,401 @@
+//// Filesystem watcher that keeps the index up to date after indexing
+// Changes go straight into the in-memory indexes, which are also what gets saved.
+// inotify needs a watch for every directory, so on Linux each indexed directory is
+// watched on its own and excluded trees like node_modules cost nothing. Other platforms
+// watch a whole root with a single handle and drop the events below excluded paths.
+
+//// Imports
+use crate::ignore_files::{is_ignore_file, IgnoreRules};
+use crate::raw_path;
+use crate::roots::{root_for_path, RootConfig};
+use crate::search::extension_weight;
+use crate::{
+    build_file_details, compile_exclusions, create_index, details_unchanged, file_name_from_path,
+    get_extensions, index_files, save_indexes, walk_start, FileDetails, FileIndex,
+    EXTENSIONS_MEMORY_INDEX, IN_MEMORY_INDEX,
+};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::Window;
+use tokio::sync::{mpsc, Mutex};
+
+//// Constants
+const COALESCE_WINDOW: Duration = Duration::from_millis(500); // Time to gather related events
+const SAVE_INTERVAL: Duration = Duration::from_secs(30); // Minimum time between index saves
+const WATCH_EACH_DIRECTORY: bool = cfg!(target_os = "linux"); // Watch directories one by one
+
+//// Global Variables
+// The active watcher, dropping it stops the event loop
+static WATCHER: Lazy<std::sync::Mutex<Option<RecommendedWatcher>>> =
+    Lazy::new(|| std::sync::Mutex::new(None));
+
+//// Data Structures
+/// What a reindex of a subtree changed in the index
+struct SubtreeChanges {
+    updated: Vec<String>,
+    removed: Vec<String>,
+    directories: Vec<PathBuf>, // Every directory below the start, for the watches
+}
+
+//// Watcher Lifecycle
+/// Starts watching every root, replacing any previous watcher.
+/// A root that can't be watched, such as an unmounted share, doesn't stop the others.
+pub fn start_watcher(
+    window: Window,
+    roots: Vec<RootConfig>,
+    file_index_path: PathBuf,
+) -> Result<(), String> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        // The receiver is gone once the watcher has been replaced
+        let _ = sender.send(event);
+    })
+    .map_err(|e| format!("Failed to create watcher: {}", e))?;
+
+    let mode = if WATCH_EACH_DIRECTORY {
+        RecursiveMode::NonRecursive
+    } else {
+        RecursiveMode::Recursive
+    };
+    for root in &roots {
+        if let Err(e) = watcher.watch(Path::new(&root.path), mode) {
+            println!("Failed to watch {}: {}", root.path, e);
+        }
+    }
+
+    *WATCHER.lock().unwrap() = Some(watcher);
+
+    tauri::async_runtime::spawn(watch_events(window, roots, file_index_path, receiver));
+
+    println!("Watching for file changes...");
+    Ok(())
+}
+
+/// Watches directories the active watcher doesn't cover yet, on platforms that watch
+/// directories one by one. Failures are summed up since they share the same watch limit.
+fn watch_directories(directories: Vec<PathBuf>) {
+    if !WATCH_EACH_DIRECTORY || directories.is_empty() {
+        return;
+    }
+    let mut watcher = WATCHER.lock().unwrap();
+    let Some(watcher) = watcher.as_mut() else {
+        return;
+    };
+
+    let mut failed = 0;
+    let mut last_error = None;
+    for directory in directories {
+        if let Err(e) = watcher.watch(&directory, RecursiveMode::NonRecursive) {
+            failed += 1;
+            last_error = Some(e);
+        }
+    }
+    if let Some(e) = last_error {
+        println!("Failed to watch {} directories: {}", failed, e);
+    }
+}
+
+/// Stops the active watcher, discarding changes that were not saved yet
+pub fn stop_watcher() {
+    if WATCHER.lock().unwrap().take().is_some() {
+        println!("Stopped watching for file changes.");
+    }
+}
+
+//// Event Handling
+/// Receives watcher events, coalesces them and applies them to both indexes
+async fn watch_events(
+    window: Window,
+    roots: Vec<RootConfig>,
+    file_index_path: PathBuf,
+    mut receiver: mpsc::UnboundedReceiver<notify::Result<Event>>,
+) {
+    // Excluded directories never make it into the index, so they are never watched
+    if WATCH_EACH_DIRECTORY {
+        let directories = IN_MEMORY_INDEX.read().await.directory_paths();
+        watch_directories(directories);
+    }
+
+    let mut dirty = false;
+    let mut last_save = Instant::now();
+
+    loop {
+        // Wait for the next event, saving pending changes while idle
+        let first_event = match tokio::time::timeout(SAVE_INTERVAL, receiver.recv()).await {
+            Ok(Some(event)) => event,
+            Ok(None) => return, // The watcher was stopped or replaced
+            Err(_) => {
+                if dirty && save_indexes(&file_index_path).await.is_ok() {
+                    dirty = false;
+                    last_save = Instant::now();
+                }
+                continue;
+            }
+        };
+
+        // Gather everything that arrives shortly after so bursts are applied at once
+        let mut changed_paths = HashSet::new();
+        let mut rescan = collect_event(first_event, &mut changed_paths);
+        let deadline = tokio::time::Instant::now() + COALESCE_WINDOW;
+        while let Ok(Some(event)) = tokio::time::timeout_at(deadline, receiver.recv()).await {
+            rescan |= collect_event(event, &mut changed_paths);
+        }
+
+        if rescan {
+            // Fall back to an incremental reindex
+            println!("Watcher event queue overflowed. Rescanning...");
+            if dirty {
+                if let Err(e) = save_indexes(&file_index_path).await {
+                    println!("Error saving index: {}", e);
+                }
+            }
+            if let Err(e) = create_index(window, file_index_path).await {
+                println!("Error rescanning files: {}", e);
+            }
+            return;
+        }
+
+        // An edited ignore file can change the ignored flag of everything next to and
+        // below it, so only that directory is checked again
+        let ignore_directories: Vec<PathBuf> = changed_paths
+            .iter()
+            .filter(|path| {
+                is_ignore_file(path)
+                    && root_for_path(&roots, path)
+                        .is_some_and(|root| root.exclusions.respect_ignore_files)
+            })
+            .filter_map(|path| path.parent().map(Path::to_path_buf))
+            .collect();
+
+        let (mut updated, mut removed) = apply_changes(&roots, changed_paths).await;
+        for directory in outermost_directories(ignore_directories) {
+            let Some(root) = root_for_path(&roots, &directory) else {
+                continue;
+            };
+            let root_folder = Path::new(&root.path);
+            if directory != root_folder
+                && compile_exclusions(root).excludes_ancestors(root_folder, &directory)
+            {
+                continue;
+            }
+            println!(
+                "Ignore files changed in {}. Rescanning it...",
+                raw_path::display(&directory)
+            );
+            let changes = reindex_subtree(root, &directory).await;
+            updated.extend(changes.updated);
+            removed.extend(changes.removed);
+        }
+        if updated.is_empty() && removed.is_empty() {
+            continue;
+        }
+        dirty = true;
+
+        window
+            .emit(
+                "index-updated",
+                json!({ "updated": updated, "removed": removed }),
+            )
+            .unwrap_or_else(|e| println!("Error emitting index update: {}", e));
+
+        if last_save.elapsed() >= SAVE_INTERVAL && save_indexes(&file_index_path).await.is_ok() {
+            dirty = false;
+            last_save = Instant::now();
+        }
+    }
+}
+
+/// Adds the paths of an event to the changed set, returning true if a rescan is needed
+fn collect_event(event: notify::Result<Event>, changed_paths: &mut HashSet<PathBuf>) -> bool {
+    match event {
+        Ok(event) => {
+            if event.need_rescan() {
+                return true;
+            }
+            if !event.kind.is_access() {
+                changed_paths.extend(event.paths);
+            }
+            false
+        }
+        Err(e) => {
+            println!("Watcher error: {}", e);
+            false
+        }
+    }
+}
+
+/// Keeps only the directories that don't lie below another one of them
+fn outermost_directories(mut directories: Vec<PathBuf>) -> Vec<PathBuf> {
+    directories.sort();
+    directories.dedup();
+    let mut outermost: Vec<PathBuf> = Vec::new();
+    for directory in directories {
+        if !outermost.iter().any(|parent| directory.starts_with(parent)) {
+            outermost.push(directory);
+        }
+    }
+    outermost
+}
+
+/// Indexes a directory and everything below it again, starting from what the index
+/// already holds there so unchanged directories are skipped like in a full run.
+/// index_files already made the entries searchable.
+async fn reindex_subtree(root: &RootConfig, path: &Path) -> SubtreeChanges {
+    let previous = {
+        let in_memory_index = IN_MEMORY_INDEX.read().await;
+        FileIndex {
+            files: in_memory_index
+                .subtree_details(&raw_path::display(path))
+                .into_iter()
+                .map(|details| (details.file_path.clone(), details))
+                .collect(),
+            rule_fingerprints: in_memory_index.rule_fingerprints().clone(),
+        }
+    };
+    let subtree = Arc::new(Mutex::new(FileIndex {
+        files: previous.files.clone(),
+        rule_fingerprints: previous.rule_fingerprints,
+    }));
+    index_files(
+        root,
+        vec![walk_start(root, path)],
+        &subtree,
+        &Arc::default(),
+    )
+    .await;
+    let subtree = std::mem::take(&mut *subtree.lock().await);
+
+    // The searchable entries were published before their directories were flagged as
+    // read, so the next run would read them again
+    let extensions = get_extensions().await.unwrap_or_default();
+    for details in subtree.files.values().filter(|details| details.scanned) {
+        insert_in_memory(details, &extensions).await;
+    }
+
+    let updated = subtree
+        .files
+        .iter()
+        .filter(|(file_path, details)| {
+            !previous
+                .files
+                .get(*file_path)
+                .is_some_and(|old| details_unchanged(old, details))
+        })
+        .map(|(file_path, _)| file_path.clone())
+        .collect();
+    let removed = previous
+        .files
+        .into_keys()
+        .filter(|file_path| !subtree.files.contains_key(file_path))
+        .collect();
+    let directories = subtree
+        .files
+        .values()
+        .filter(|details| details.file_type == "directory")
+        .map(FileDetails::path)
+        .collect();
+    SubtreeChanges {
+        updated,
+        removed,
+        directories,
+    }
+}
+
+/// Adds or replaces an entry in the in-memory indexes
+async fn insert_in_memory(details: &FileDetails, extensions: &HashMap<String, i64>) {
+    IN_MEMORY_INDEX.write().await.insert(details);
+    if extension_weight(&details.file_extension, extensions).is_some() {
+        EXTENSIONS_MEMORY_INDEX.write().await.insert(details);
+    }
+}
+
+/// Removes a path from both indexes, including everything below it if it was a directory.
+/// Returns false if the path was never indexed.
+async fn remove_entry(file_path: &str) -> bool {
+    EXTENSIONS_MEMORY_INDEX
+        .write()
+        .await
+        .remove_subtree(file_path);
+    IN_MEMORY_INDEX.write().await.remove_subtree(file_path)
+}
+
+/// Looks up the current state of every changed path and updates both indexes.
+/// Returns the updated and the removed paths.
+async fn apply_changes(
+    roots: &[RootConfig],
+    changed_paths: HashSet<PathBuf>,
+) -> (Vec<String>, Vec<String>) {
+    let mut updated = Vec::new();
+    let mut removed = Vec::new();
+    let extensions = get_extensions().await.unwrap_or_default();
+    let compiled_rules: Vec<_> = roots.iter().map(compile_exclusions).collect();
+    let mut ignore_rules: HashMap<PathBuf, (Arc<IgnoreRules>, bool)> = HashMap::new();
+
+    for path in changed_paths {
+        let Some(position) = roots.iter().position(|root| root.contains(&path)) else {
+            continue;
+        };
+        let (root, rules) = (&roots[position], &compiled_rules[position]);
+        let root_folder = Path::new(&root.path);
+
+        // Skip the same paths index_files would never reach
+        if path == root_folder || rules.excludes_ancestors(root_folder, &path) {
+            continue;
+        }
+        let file_path = raw_path::display(&path);
+
+        match tokio::fs::symlink_metadata(&path).await {
+            Ok(metadata) if rules.excludes(&path, metadata.is_dir(), metadata.len()) => {
+                // The entry may have been indexed before it grew past the size limit
+                if remove_entry(&file_path).await {
+                    removed.push(file_path);
+                }
+            }
+            Ok(metadata) => {
+                // Directories moved in from elsewhere arrive as a single event
+                if metadata.is_dir() && !IN_MEMORY_INDEX.read().await.contains(&file_path) {
+                    let mut changes = reindex_subtree(root, &path).await;
+                    changes.directories.push(path.clone());
+                    watch_directories(changes.directories);
+                    updated.extend(changes.updated);
+                }
+
+                // Rebuilding the details doesn't read the directory, so keep whether
+                // index_files or an earlier run did
+                let mut details =
+                    build_file_details(&path, file_name_from_path(&file_path), &metadata);
+                if metadata.is_dir() {
+                    details.scanned = IN_MEMORY_INDEX
+                        .read()
+                        .await
+                        .get(&file_path)
+                        .is_some_and(|existing| existing.scanned);
+                }
+                if let Some(parent) = path.parent().filter(|_| rules.respect_ignore_files()) {
+                    // Changes often come in bursts within the same directory
+                    let (parent_rules, parent_ignored) = ignore_rules
+                        .entry(parent.to_path_buf())
+                        .or_insert_with(|| IgnoreRules::for_path(root_folder, parent));
+                    details.ignored =
+                        *parent_ignored || parent_rules.is_ignored(&path, metadata.is_dir());
+                }
+                insert_in_memory(&details, &extensions).await;
+                updated.push(file_path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if remove_entry(&file_path).await {
+                    removed.push(file_path);
+                }
+            }
+            Err(e) => println!("Error getting metadata for {}: {}", file_path, e),
+        }
+    }
+
+    (updated, removed)
+}
@@ -1,4 +1,4 @@
-import React, { useState, useCallback, useEffect } from "react";
+import React, { useState, useCallback, useEffect, useRef } from "react";
 import { invoke } from "@tauri-apps/api/tauri";
 import { listen } from "@tauri-apps/api/event";
 import debounce from "lodash.debounce";
@@ -7,6 +7,7 @@ import { ThemeProvider } from "@/components/theme-provider";
 import { Navbar } from "@/components/Navbar";
 import { ViewPage } from "@/components/ViewPage";
 import { SetupPage } from "@/components/SetupPage";
+import { Button } from "@/components/ui/button";
 // import { i } from "vite/dist/node/types.d-aGj9QkWt";
 import {
   Folder,
@@ -14,6 +15,8 @@ import {
 
 // TODO: Add compacting to Queue
 
+const PAGE_SIZE = 200; // Results fetched per page
+
 class Queue {
   constructor() {
     this.items = [];
@@ -76,6 +79,16 @@ function App() {
   const [recent, setRecent] = useState(new Queue());
   const [keyFolders, setKeyFolders] = useState({});
   const [selectedFile, setSelectedFile] = useState(null);
+  const [indexStatus, setIndexStatus] = useState(null);
+  const [partial, setPartial] = useState(false);
+  const [total, setTotal] = useState(0);
+  const queryRef = useRef("");
+  const queryIdRef = useRef(0); // Id of the latest streamed search, older batches are dropped
+  const [searchOptions, setSearchOptions] = useState({
+    caseSensitive: false,
+    accentSensitive: false,
+  });
+  const searchOptionsRef = useRef(searchOptions); // Read by the debounced search
 
   useEffect(() => {
     startup();
@@ -135,38 +148,150 @@ function App() {
       listen("indexing-started", () => {
         console.log("Indexing started");
       });
+
+      // Throttled counters of the running indexing run
+      listen("indexing-progress", (event) => {
+        setIndexStatus(event.payload);
+      });
   
       listen("indexing-completed", () => {
         console.log("Indexing completed");
         setStart(true);
       });
+
+      // A stopped run keeps what it found, so search what is there
+      listen("indexing-paused", () => {
+        console.log("Indexing paused");
+        setStart(true);
+      });
+
+      listen("indexing-cancelled", () => {
+        console.log("Indexing cancelled");
+        setStart(true);
+      });
+
+      // A damaged index or settings file was found while loading
+      listen("storage-error", (event) => {
+        const { file, message, recovered } = event.payload;
+        console.error(`Error loading ${file}: ${message}`);
+        setError(
+          recovered
+            ? `${file} was damaged and has been restored from its backup.`
+            : `${file} was damaged and could not be restored.`
+        );
+      });
+
+      // Ranked batches of the streamed search, the best matches found so far
+      listen("search-results", (event) => {
+        const batch = event.payload;
+        if (batch.query_id !== queryIdRef.current) {
+          return; // A newer query has started since
+        }
+        setResults(formatResults(batch.results));
+        setTotal(batch.total);
+        setPartial(batch.partial);
+        if (batch.done) {
+          setLoading(false);
+        }
+      });
+
+      // Refresh the open results when the watcher changes the index
+      listen("index-updated", () => {
+        if (queryRef.current.trim() !== "") {
+          debouncedFetchResults(queryRef.current);
+        }
+      });
     } catch (error) {
       console.error("Error starting up: ", error);
     }
   };
 
+  // Transform the data structure to better work with React
+  const formatResults = (results) =>
+    results.map((result) => ({
+      fileName: result.file_name,
+      filePath: result.file_path,
+      root: result.root,
+      score: result.score,
+      nameRanges: result.name_ranges, // Matched characters, for highlighting
+      pathRanges: result.path_ranges,
+    }));
+
+  const showSearchError = (error) => {
+    if (error?.kind === "cancelled") {
+      return; // A newer search took over
+    }
+    console.error("Error searching files:", error);
+    if (error?.kind === "invalid_query") {
+      // Each diagnostic carries the start and end character of the problem
+      setError(error.diagnostics.map((diagnostic) => diagnostic.message).join(" "));
+    } else {
+      setError("Failed to search files.");
+    }
+  };
+
+  // Starts a streamed search, its results arrive as "search-results" events
   const fetchResults = async (query) => {
+    queryIdRef.current += 1;
+    const queryId = queryIdRef.current;
     setLoading(true);
     setError(null);
     try {
-      // Returns an array of objects with filename and details
-      const searchResults = await invoke("search_files", { query });
+      await invoke("stream_search", {
+        queryId,
+        query,
+        limit: PAGE_SIZE,
+        ...searchOptionsRef.current,
+      });
+    } catch (error) {
+      if (queryId === queryIdRef.current) {
+        showSearchError(error);
+      }
+    } finally {
+      if (queryId === queryIdRef.current) {
+        setLoading(false);
+      }
+    }
+  };
 
-      // Transform the data structure to better work with React
-      const formattedResults = searchResults.map(([file_name, file_path]) => ({
-        fileName: file_name,
-        filePath: file_path,
-      }));
+  // Fetches the page of results starting at offset and appends it to the shown results
+  const fetchPage = async (query, offset) => {
+    setLoading(true);
+    setError(null);
+    try {
+      // Returns one page of matches, their total and whether indexing is still running
+      const searchResults = await invoke("search_files", {
+        query,
+        limit: PAGE_SIZE,
+        offset,
+        ...searchOptionsRef.current,
+      });
 
-      setResults(formattedResults);
+      setResults((previous) => [...previous, ...formatResults(searchResults.results)]);
+      setTotal(searchResults.total);
+      setPartial(searchResults.partial);
     } catch (error) {
-      console.error("Error searching files:", error);
-      setError("Failed to search files.");
+      showSearchError(error);
     } finally {
       setLoading(false);
     }
   };
 
+  const loadMore = () => fetchPage(queryRef.current, results.length);
+
+  // Flips a case or accent toggle and searches again with it
+  const toggleOption = (option) => {
+    const options = {
+      ...searchOptionsRef.current,
+      [option]: !searchOptionsRef.current[option],
+    };
+    searchOptionsRef.current = options;
+    setSearchOptions(options);
+    if (queryRef.current.trim() !== "") {
+      fetchResults(queryRef.current);
+    }
+  };
+
   const debouncedFetchResults = useCallback(
     debounce((query) => fetchResults(query), 300),
     []
@@ -175,7 +300,9 @@ function App() {
   const handleChange = (e) => {
     const newQuery = e.target.value;
     setQuery(newQuery);
+    queryRef.current = newQuery;
     if (newQuery.trim() === "") {
+      queryIdRef.current += 1; // Drop batches of the last search
       setResults([]);
     } else {
       debouncedFetchResults(newQuery);
@@ -288,6 +415,44 @@ function App() {
           `}</style>
         </div>
         <p className="mt-4 text-xl">Indexing Files</p>
+        {indexStatus && (
+          <div className="mt-2 text-sm text-muted-foreground text-center max-w-lg">
+            <p>
+              {indexStatus.files_indexed.toLocaleString()} files in{" "}
+              {indexStatus.directories_scanned.toLocaleString()} folders
+              {indexStatus.errors_skipped > 0 &&
+                `, ${indexStatus.errors_skipped} skipped`}
+              {indexStatus.estimated_remaining_ms != null &&
+                ` · about ${Math.ceil(indexStatus.estimated_remaining_ms / 1000)}s left`}
+            </p>
+            {indexStatus.current_path && (
+              <p className="truncate">{indexStatus.current_path}</p>
+            )}
+          </div>
+        )}
+        <div className="mt-4 flex gap-2">
+          <Button onClick={() => setStart(true)}>Search now</Button>
+          <Button
+            variant="outline"
+            onClick={() =>
+              invoke("pause_indexing").catch((error) =>
+                console.error("Failed to pause indexing:", error)
+              )
+            }
+          >
+            Pause
+          </Button>
+          <Button
+            variant="outline"
+            onClick={() =>
+              invoke("cancel_indexing").catch((error) =>
+                console.error("Failed to cancel indexing:", error)
+              )
+            }
+          >
+            Cancel
+          </Button>
+        </div>
       </div>
       )}
       {setup && start && (
@@ -297,6 +462,9 @@ function App() {
             results={results}
             loading={loading}
             error={error}
+            partial={partial}
+            total={total}
+            loadMore={loadMore}
             query={query}
             openFile={openFile}
             recent={recent}
@@ -305,6 +473,8 @@ function App() {
             selectedFile={selectedFile}
             setSelectedFile={setSelectedFile}
             keyFolders={keyFolders}
+            searchOptions={searchOptions}
+            toggleOption={toggleOption}
           ></ViewPage>
         </>
       )}
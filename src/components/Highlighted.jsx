@@ -0,0 +1,27 @@
+// Renders text with the given character ranges in bold, ranges are [start, end)
+export const Highlighted = ({ text, ranges = [] }) => {
+  // Index by character rather than UTF-16 unit, like the ranges from search_files
+  const chars = Array.from(text);
+  const parts = [];
+  let position = 0;
+
+  ranges.forEach(([start, end]) => {
+    if (start >= chars.length) {
+      return;
+    }
+    if (start > position) {
+      parts.push(chars.slice(position, start).join(""));
+    }
+    parts.push(
+      <b key={start} className="font-bold text-primary">
+        {chars.slice(start, end).join("")}
+      </b>
+    );
+    position = end;
+  });
+  if (position < chars.length) {
+    parts.push(chars.slice(position).join(""));
+  }
+
+  return <>{parts}</>;
+};
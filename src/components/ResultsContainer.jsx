@@ -1,8 +1,14 @@
+import { Button } from "@/components/ui/button";
+import { Highlighted } from "@/components/Highlighted";
+
 export const ResultsContainer = ({
   className,
   results,
   loading,
   error,
+  partial,
+  total,
+  loadMore,
   query,
   openFile,
   getFileIcon,
@@ -31,6 +37,11 @@ export const ResultsContainer = ({
 
   return (
     <section className={className}>
+      {partial && (
+        <p className="text-muted-foreground text-sm mb-4">
+          Indexing is still running, so some files may be missing.
+        </p>
+      )}
       {results.length === 0 && !loading && !error && query.trim() !== "" && (
         <p className="text-muted-foreground">No files to show</p>
       )}
@@ -49,13 +60,26 @@ export const ResultsContainer = ({
                 alt={file.fileName}
                 className="w-8 h-8 sm:w-12 sm:h-12 md:w-16 md:h-16 lg:w-20 lg:h-20 xl:w-24 xl:h-24"
               />
-              <p className="truncate w-full text-center text-sm">
-                {file.fileName.split(".")[0]}
+              <p className="truncate w-full text-center text-sm" title={file.filePath}>
+                <Highlighted
+                  text={file.fileName.split(".")[0]}
+                  ranges={file.nameRanges}
+                />
               </p>
             </div>
           ))}
         </div>
       )}
+      {total > results.length && (
+        <div className="flex flex-col items-center gap-2 mt-6">
+          <p className="text-muted-foreground text-sm">
+            Showing {results.length.toLocaleString()} of {total.toLocaleString()} files
+          </p>
+          <Button variant="outline" disabled={loading} onClick={loadMore}>
+            Show more
+          </Button>
+        </div>
+      )}
     </section>
   );
 };
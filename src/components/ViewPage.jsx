@@ -11,6 +11,9 @@ export const ViewPage = ({
   results,
   loading,
   error,
+  partial,
+  total,
+  loadMore,
   query,
   openFile,
   recent,
@@ -19,10 +22,19 @@ export const ViewPage = ({
   selectedFile,
   setSelectedFile,
   keyFolders,
+  searchOptions,
+  toggleOption,
 }) => {
   return (
     <SidebarProvider>
-      <AppSidebar query={query} handleChange={handleChange} keyFolders={keyFolders} openFile={openFile}/>
+      <AppSidebar
+        query={query}
+        handleChange={handleChange}
+        keyFolders={keyFolders}
+        openFile={openFile}
+        searchOptions={searchOptions}
+        toggleOption={toggleOption}
+      />
       <SidebarInset>
         <main>
           <Navbar text={query != "" ? "Results" : "Recent Files"} path={selectedFile === null ? null : selectedFile.filePath}/>
@@ -30,6 +42,9 @@ export const ViewPage = ({
             results={query != "" ? results : recent.getItems()}
             loading={loading}
             error={error}
+            partial={partial && query != ""}
+            total={query != "" ? total : 0}
+            loadMore={loadMore}
             query={query}
             openFile={openFile}
             getFileIcon={getFileIcon}
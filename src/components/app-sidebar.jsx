@@ -32,7 +32,12 @@ export function AppSidebar({ ...props }) {
   return (
     <Sidebar collapsible="offcanvas" {...props}>
       <SidebarHeader>
-        <SearchForm query={props.query} handleChange={props.handleChange}/>
+        <SearchForm
+          query={props.query}
+          handleChange={props.handleChange}
+          searchOptions={props.searchOptions}
+          toggleOption={props.toggleOption}
+        />
       </SidebarHeader>
       <SidebarContent className="">
         <SidebarGroupItems items={data.quickAccess} groupTitle={"Quick Access"} openFile={props.openFile}/>
@@ -1,12 +1,13 @@
 import { Search } from "lucide-react"
 import { Label } from "@/components/ui/label"
+import { Toggle } from "@/components/ui/toggle"
 import {
   SidebarGroup,
   SidebarGroupContent,
   SidebarInput,
 } from "@/components/ui/sidebar"
 
-export function SearchForm({ ...props }) {
+export function SearchForm({ searchOptions, toggleOption, ...props }) {
   return (
     <form {...props}>
       <SidebarGroup className="py-0">
@@ -23,6 +24,26 @@ export function SearchForm({ ...props }) {
           />
           <Search className="pointer-events-none absolute left-2 top-1/2 size-4 -translate-y-1/2 select-none opacity-50" />
         </SidebarGroupContent>
+        {searchOptions && (
+          <SidebarGroupContent className="flex gap-1 pt-1">
+            <Toggle
+              size="sm"
+              title="Match case"
+              pressed={searchOptions.caseSensitive}
+              onPressedChange={() => toggleOption("caseSensitive")}
+            >
+              Aa
+            </Toggle>
+            <Toggle
+              size="sm"
+              title="Match accents"
+              pressed={searchOptions.accentSensitive}
+              onPressedChange={() => toggleOption("accentSensitive")}
+            >
+              é
+            </Toggle>
+          </SidebarGroupContent>
+        )}
       </SidebarGroup>
     </form>
   )